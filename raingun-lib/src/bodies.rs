@@ -1,10 +1,20 @@
+use boxes::{OrientedBox, RoundedBox};
 use color::Color;
+use csg::{self, Csg, CsgOperation};
+use cylinders::{Capsule, Cone, Cylinder};
+use heightfield::Heightfield;
 use material::*;
+use mesh::Mesh;
 use ray::Ray;
+use sdf::Sdf;
+use torus::Torus;
+use transform::Transform;
 use super::{Point3, Vector3};
 use cgmath::prelude::*;
 
+use std::collections::BTreeMap;
 use std::f32::consts::PI;
+use std::sync::Arc;
 
 fn is_close(a: f64, b: f64) -> bool {
     (a - b).abs() < 1e-8
@@ -15,6 +25,8 @@ pub struct Sphere {
     pub center: Point3,
     pub radius: f64,
     pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -22,49 +34,397 @@ pub struct Plane {
     pub origin: Point3,
     pub normal: Vector3,
     pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Disk {
     pub origin: Point3,
     pub normal: Vector3,
     pub radius: f64,
+    // Makes a ring with a hole of this radius in the middle.
+    #[serde(default)]
+    pub inner_radius: f64,
     pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
 }
 
+// A rectangle with a corner at the origin, and sides along the two edges. It is a parallelogram
+// when the edges aren't at a right angle. Like triangles it can be seen from both sides, and the
+// front is the side where the second edge is counter-clockwise from the first one.
 #[derive(Debug, Clone, Deserialize)]
+pub struct Rectangle {
+    pub origin: Point3,
+    pub edges: [Vector3; 2],
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct AABB {
     pub bounds: [Point3; 2],
+    #[serde(default)]
+    pub texture_layout: BoxTextureLayout,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+// How a texture is put on the faces of a box.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+pub enum BoxTextureLayout {
+    // The whole texture on every face.
+    #[default]
+    Repeated,
+    // The texture is split into a grid of four by three, with one face in each part of a cross:
+    //
+    //           top
+    //     left  front  right  back
+    //           bottom
+    //
+    // The front faces +Z, the right +X and the top +Y.
+    Cross,
+}
+
+// The front side of a triangle is the one where its vertices are in counter-clockwise order.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Triangle {
+    pub vertices: [Point3; 3],
+    // Per-vertex normals, interpolated for smooth shading. Without them the triangle is flat.
+    #[serde(default)]
+    pub normals: Option<[Vector3; 3]>,
+    // Per-vertex texture coordinates. Without them the barycentric coordinates are used.
+    #[serde(default)]
+    pub texture_coords: Option<[TextureCoords; 3]>,
     pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+// A copy of one of the scene's named geometries. The geometry's bodies are shared between all of
+// its instances rather than copied, so many instances don't take up much more memory than one.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Instance {
+    pub geometry: String,
+    // Replaces the materials of all of the geometry's bodies.
+    #[serde(default)]
+    pub material: Option<Material>,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    // The geometry's bodies, filled in by Body::resolve_instances when the scene is loaded.
+    #[serde(skip_deserializing)]
+    pub bodies: Arc<Vec<Body>>,
 }
 
+// Bodies can have a transform that places them in the scene, in which case their other fields are
+// in object space.
 #[derive(Debug, Clone, Deserialize)]
 pub enum Body {
     Sphere(Sphere),
     Plane(Plane),
     Disk(Disk),
+    Rectangle(Rectangle),
     AABB(AABB),
+    OrientedBox(OrientedBox),
+    RoundedBox(RoundedBox),
+    Triangle(Triangle),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Capsule(Capsule),
+    Torus(Torus),
+    Heightfield(Heightfield),
+    Sdf(Sdf),
+    Mesh(Mesh),
+    Instance(Instance),
+    Csg(Csg),
 }
 
 impl Body {
-    pub fn material(&self) -> &Material {
-        match *self {
+    // None for an instance without a material of its own that isn't linked to its geometry yet.
+    pub fn material(&self) -> Option<&Material> {
+        let material = match *self {
             Body::Sphere(ref sphere) => &sphere.material,
             Body::Plane(ref plane) => &plane.material,
             Body::Disk(ref disk) => &disk.material,
+            Body::Rectangle(ref rectangle) => &rectangle.material,
             Body::AABB(ref aabb) => &aabb.material,
+            Body::OrientedBox(ref oriented_box) => &oriented_box.material,
+            Body::RoundedBox(ref rounded_box) => &rounded_box.material,
+            Body::Triangle(ref triangle) => &triangle.material,
+            Body::Cylinder(ref cylinder) => &cylinder.material,
+            Body::Cone(ref cone) => &cone.material,
+            Body::Capsule(ref capsule) => &capsule.material,
+            Body::Torus(ref torus) => &torus.material,
+            Body::Heightfield(ref heightfield) => heightfield.material(),
+            Body::Sdf(ref sdf) => &sdf.material,
+            // Parts of the mesh can have their own materials from the mesh file.
+            Body::Mesh(ref mesh) => mesh.material(),
+            Body::Instance(ref instance) => return instance.material(),
+            Body::Csg(ref csg) => return csg.material(),
+        };
+        Some(material)
+    }
+
+    pub fn color(&self, texture_coords: &TextureCoords) -> Option<Color> {
+        self.material().map(|material| material.color(texture_coords))
+    }
+
+    pub fn albedo(&self) -> Option<f32> {
+        self.material().map(|material| material.albedo)
+    }
+
+    // Links the instances in the body, including those in the bodies that it combines, to the
+    // geometries that they refer to.
+    pub fn resolve_instances(&mut self,
+                             geometries: &BTreeMap<String, Arc<Vec<Body>>>)
+                             -> Result<(), String> {
+        match *self {
+            Body::Instance(ref mut instance) => {
+                match geometries.get(&instance.geometry) {
+                    Some(bodies) => {
+                        instance.bodies = bodies.clone();
+                        Ok(())
+                    }
+                    None => Err(format!("Scene has no geometry named \"{}\"", instance.geometry)),
+                }
+            }
+            Body::Csg(ref mut csg) => csg.resolve_instances(geometries),
+            _ => Ok(()),
+        }
+    }
+
+    // Whether the body is or contains an instance.
+    pub fn has_instances(&self) -> bool {
+        match *self {
+            Body::Instance(_) => true,
+            Body::Csg(ref csg) => csg.bodies().iter().any(Body::has_instances),
+            _ => false,
+        }
+    }
+
+    pub fn transform(&self) -> Option<&Transform> {
+        match *self {
+            Body::Sphere(ref sphere) => sphere.transform.as_ref(),
+            Body::Plane(ref plane) => plane.transform.as_ref(),
+            Body::Disk(ref disk) => disk.transform.as_ref(),
+            Body::Rectangle(ref rectangle) => rectangle.transform.as_ref(),
+            Body::AABB(ref aabb) => aabb.transform.as_ref(),
+            Body::OrientedBox(ref oriented_box) => oriented_box.transform.as_ref(),
+            Body::RoundedBox(ref rounded_box) => rounded_box.transform.as_ref(),
+            Body::Triangle(ref triangle) => triangle.transform.as_ref(),
+            Body::Cylinder(ref cylinder) => cylinder.transform.as_ref(),
+            Body::Cone(ref cone) => cone.transform.as_ref(),
+            Body::Capsule(ref capsule) => capsule.transform.as_ref(),
+            Body::Torus(ref torus) => torus.transform.as_ref(),
+            Body::Heightfield(ref heightfield) => heightfield.transform(),
+            Body::Sdf(ref sdf) => sdf.transform.as_ref(),
+            // Meshes are transformed when they are loaded.
+            Body::Mesh(_) => None,
+            Body::Instance(ref instance) => instance.transform.as_ref(),
+            Body::Csg(ref csg) => csg.transform(),
+        }
+    }
+
+    // Whether the body encloses a volume, so that it has spans and can be used in CSG bodies.
+    pub fn is_solid(&self) -> bool {
+        match *self {
+            Body::Sphere(_) | Body::Plane(_) | Body::AABB(_) | Body::OrientedBox(_) |
+            Body::RoundedBox(_) | Body::Capsule(_) | Body::Torus(_) | Body::Csg(_) => true,
+            // Without caps they are open at the ends.
+            Body::Cylinder(ref cylinder) => cylinder.caps,
+            Body::Cone(ref cone) => cone.caps,
+            // Until it is linked to its geometry an instance counts as solid, so that it can be
+            // combined. Linking it checks again.
+            Body::Instance(ref instance) => instance.bodies.iter().all(Body::is_solid),
+            _ => false,
         }
     }
 
-    pub fn color(&self, texture_coords: &TextureCoords) -> Color {
-        self.material().color(texture_coords)
+    // Finds where the ray hits the body, along with what its surface looks like there.
+    pub fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        match self.transform() {
+            None => self.object_intersection(ray),
+            Some(transform) => {
+                // Intersect in object space, and move the normal back out to world space.
+                let (object_ray, scale) = transform.inverse_transform_ray(ray);
+                self.object_intersection(&object_ray)
+                    .map(|intersection| to_world_space(intersection, transform, scale))
+            }
+        }
+    }
+
+    // Finds where the ray passes through the body, including behind its origin. Bodies that
+    // aren't solid have no spans.
+    pub fn spans(&self, ray: &Ray) -> Vec<Span<'_>> {
+        match self.transform() {
+            None => self.object_spans(ray),
+            Some(transform) => {
+                let (object_ray, scale) = transform.inverse_transform_ray(ray);
+                self.object_spans(&object_ray)
+                    .into_iter()
+                    .map(|span| {
+                             Span {
+                                 entry: to_world_space(span.entry, transform, scale),
+                                 exit: to_world_space(span.exit, transform, scale),
+                             }
+                         })
+                    .collect()
+            }
+        }
+    }
+
+    fn object_spans<'a>(&'a self, ray: &Ray) -> Vec<Span<'a>> {
+        match *self {
+            Body::Sphere(ref sphere) => solid_spans(self, sphere, ray, &sphere.material),
+            Body::Plane(ref plane) => solid_spans(self, plane, ray, &plane.material),
+            Body::AABB(ref aabb) => solid_spans(self, aabb, ray, &aabb.material),
+            Body::OrientedBox(ref oriented_box) => {
+                solid_spans(self, oriented_box, ray, &oriented_box.material)
+            }
+            Body::RoundedBox(ref rounded_box) => {
+                solid_spans(self, rounded_box, ray, &rounded_box.material)
+            }
+            Body::Cylinder(ref cylinder) => solid_spans(self, cylinder, ray, &cylinder.material),
+            Body::Cone(ref cone) => solid_spans(self, cone, ray, &cone.material),
+            Body::Capsule(ref capsule) => solid_spans(self, capsule, ray, &capsule.material),
+            Body::Torus(ref torus) => solid_spans(self, torus, ray, &torus.material),
+            Body::Instance(ref instance) => {
+                // The geometry's bodies make up one solid together.
+                let spans = instance.bodies
+                    .iter()
+                    .fold(Vec::new(), |spans, body| {
+                        csg::combine(CsgOperation::Union, spans, body.spans(ray))
+                    });
+                let instance_intersection = |intersection: Intersection<'a>| {
+                    Intersection {
+                        body: self,
+                        material: instance.material.as_ref().unwrap_or(intersection.material),
+                        ..intersection
+                    }
+                };
+                spans
+                    .into_iter()
+                    .map(|span| {
+                             Span {
+                                 entry: instance_intersection(span.entry),
+                                 exit: instance_intersection(span.exit),
+                             }
+                         })
+                    .collect()
+            }
+            Body::Csg(ref csg) => {
+                csg.spans(ray)
+                    .into_iter()
+                    .map(|span| {
+                             Span {
+                                 entry: Intersection { body: self, ..span.entry },
+                                 exit: Intersection { body: self, ..span.exit },
+                             }
+                         })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn object_intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let hit = match *self {
+            Body::Sphere(ref sphere) => hit_details(sphere, ray, &sphere.material),
+            Body::Plane(ref plane) => hit_details(plane, ray, &plane.material),
+            Body::Disk(ref disk) => hit_details(disk, ray, &disk.material),
+            Body::Rectangle(ref rectangle) => hit_details(rectangle, ray, &rectangle.material),
+            Body::AABB(ref aabb) => hit_details(aabb, ray, &aabb.material),
+            Body::OrientedBox(ref oriented_box) => {
+                hit_details(oriented_box, ray, &oriented_box.material)
+            }
+            Body::RoundedBox(ref rounded_box) => {
+                hit_details(rounded_box, ray, &rounded_box.material)
+            }
+            Body::Triangle(ref triangle) => hit_details(triangle, ray, &triangle.material),
+            Body::Cylinder(ref cylinder) => hit_details(cylinder, ray, &cylinder.material),
+            Body::Cone(ref cone) => hit_details(cone, ray, &cone.material),
+            Body::Capsule(ref capsule) => hit_details(capsule, ray, &capsule.material),
+            Body::Torus(ref torus) => hit_details(torus, ray, &torus.material),
+            Body::Heightfield(ref heightfield) => {
+                hit_details(heightfield, ray, heightfield.material())
+            }
+            Body::Sdf(ref sdf) => hit_details(sdf, ray, &sdf.material),
+            Body::Mesh(ref mesh) => {
+                mesh.intersect(ray).map(|(distance, face)| {
+                    let hit_point = ray.origin + (ray.direction * distance);
+                    (distance,
+                     face.surface_normal(&hit_point),
+                     face.texture_coords(&hit_point),
+                     mesh.face_material(face))
+                })
+            }
+            Body::Instance(ref instance) => {
+                // The instance is what was hit as far as the scene is concerned, not one of the
+                // bodies that it shares with other instances.
+                return closest_intersection(&instance.bodies, ray).map(|intersection| {
+                    Intersection {
+                        body: self,
+                        material: instance.material.as_ref().unwrap_or(intersection.material),
+                        ..intersection
+                    }
+                });
+            }
+            Body::Csg(ref csg) => {
+                // The first surface in front of the ray, which is an exit when it starts inside.
+                // Spans of planes reach infinitely far, but there is no surface to hit there.
+                let in_front = |distance: f64| distance.is_finite() && distance >= 0.0;
+                for span in csg.spans(ray) {
+                    let intersection = if in_front(span.entry.distance) {
+                        span.entry
+                    } else if in_front(span.exit.distance) {
+                        span.exit
+                    } else {
+                        continue;
+                    };
+                    return Some(Intersection { body: self, ..intersection });
+                }
+                return None;
+            }
+        };
+
+        hit.map(|(distance, normal, texture_coords, material)| {
+            Intersection {
+                distance,
+                body: self,
+                normal,
+                texture_coords,
+                material,
+            }
+        })
     }
+}
 
-    pub fn albedo(&self) -> f32 {
-        self.material().albedo
+impl Instance {
+    // Without a material of its own, the material of the geometry's first body stands in for the
+    // whole geometry, once the instance is linked to it. Hits still use the material of the body
+    // that was hit.
+    pub fn material(&self) -> Option<&Material> {
+        match self.material {
+            Some(ref material) => Some(material),
+            None => self.bodies.first().and_then(Body::material),
+        }
     }
 }
 
+// Finds the closest of the bodies that the ray hits.
+pub fn closest_intersection<'a>(bodies: &'a [Body], ray: &Ray) -> Option<Intersection<'a>> {
+    bodies
+        .iter()
+        .filter_map(|body| body.intersection(ray))
+        .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
+}
+
 pub trait Intersectable {
     fn intersect(&self, ray: &Ray) -> Option<f64>;
 
@@ -72,6 +432,13 @@ pub trait Intersectable {
     fn texture_coords(&self, hit_point: &Point3) -> TextureCoords;
 }
 
+// Shapes that enclose a volume, which rays enter and exit.
+pub trait Solid: Intersectable {
+    // Distances along the ray to where it enters and exits the shape, in order. Unlike intersect
+    // these can be behind the ray's origin, and can be infinite for unbounded shapes.
+    fn span_distances(&self, ray: &Ray) -> Vec<(f64, f64)>;
+}
+
 impl Intersectable for Sphere {
     fn intersect(&self, ray: &Ray) -> Option<f64> {
         // How to determine if we intersect:
@@ -132,6 +499,23 @@ impl Intersectable for Sphere {
     }
 }
 
+impl Solid for Sphere {
+    fn span_distances(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        // The same as in intersect, without leaving out what is behind the ray.
+        let hypothenuse = self.center - ray.origin;
+        let adjacent = hypothenuse.dot(ray.direction);
+        let opposite_squared = hypothenuse.dot(hypothenuse) - (adjacent * adjacent);
+        let radius_squared = self.radius * self.radius;
+
+        if opposite_squared > radius_squared {
+            return Vec::new();
+        }
+
+        let thickness = (radius_squared - opposite_squared).sqrt();
+        vec![(adjacent - thickness, adjacent + thickness)]
+    }
+}
+
 impl Intersectable for Plane {
     fn intersect(&self, ray: &Ray) -> Option<f64> {
         let denominator = self.normal.dot(ray.direction);
@@ -153,18 +537,30 @@ impl Intersectable for Plane {
     }
 
     fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
-        let mut x_axis = self.normal.cross(Vector3::unit_z());
-
-        if x_axis.magnitude2() == 0.0 {
-            x_axis = self.normal.cross(Vector3::unit_y());
-        }
+        planar_texture_coords(&self.origin, &self.normal, hit_point)
+    }
+}
 
-        let y_axis = self.normal.cross(x_axis);
+// The inside of a plane is the side that its normal points to, so that it is below the floor when
+// the normal points down.
+impl Solid for Plane {
+    fn span_distances(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        let denominator = self.normal.dot(ray.direction);
+        let height = (ray.origin - self.origin).dot(self.normal);
 
-        let hit_vec = hit_point - self.origin;
-        TextureCoords {
-            x: hit_vec.dot(x_axis) as f32,
-            y: hit_vec.dot(y_axis) as f32,
+        if denominator.abs() <= 1e-6 {
+            if height > 0.0 {
+                vec![(f64::NEG_INFINITY, f64::INFINITY)]
+            } else {
+                Vec::new()
+            }
+        } else {
+            let distance = -height / denominator;
+            if denominator > 0.0 {
+                vec![(distance, f64::INFINITY)]
+            } else {
+                vec![(f64::NEG_INFINITY, distance)]
+            }
         }
     }
 }
@@ -182,7 +578,8 @@ impl Intersectable for Disk {
                 let d2 = v.dot(v);
                 // TODO: Figure out a way of storing a precomputed self.radius_squared to optimize
                 // away the sqrt() call.
-                if d2.sqrt() < self.radius {
+                let hit_radius = d2.sqrt();
+                if hit_radius < self.radius && hit_radius >= self.inner_radius {
                     return Some(distance);
                 }
             }
@@ -196,20 +593,50 @@ impl Intersectable for Disk {
     }
 
     fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
-        let mut x_axis = self.normal.cross(Vector3::unit_z());
+        planar_texture_coords(&self.origin, &self.normal, hit_point)
+    }
+}
 
-        if x_axis.magnitude2() == 0.0 {
-            x_axis = self.normal.cross(Vector3::unit_y());
+impl Rectangle {
+    // The normal on the front side.
+    pub fn face_normal(&self) -> Vector3 {
+        self.edges[0].cross(self.edges[1]).normalize()
+    }
+}
+
+impl Intersectable for Rectangle {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let normal = self.edges[0].cross(self.edges[1]);
+        let denominator = normal.dot(ray.direction);
+        if denominator.abs() < 1e-12 {
+            return None;
         }
 
-        let y_axis = self.normal.cross(x_axis);
+        let distance = (self.origin - ray.origin).dot(normal) / denominator;
+        if distance < 0.0 {
+            return None;
+        }
 
-        let hit_vec = hit_point - self.origin;
-        TextureCoords {
-            x: hit_vec.dot(x_axis) as f32,
-            y: hit_vec.dot(y_axis) as f32,
+        // How far along each edge the hit point is, as in hit_vec = x * edges[0] + y * edges[1].
+        let hit_vec = ray.origin + ray.direction * distance - self.origin;
+        let x = hit_vec.cross(self.edges[1]).dot(normal) / normal.magnitude2();
+        let y = self.edges[0].cross(hit_vec).dot(normal) / normal.magnitude2();
+
+        if (0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y) {
+            Some(distance)
+        } else {
+            None
         }
     }
+
+    fn surface_normal(&self, _hit_point: &Point3) -> Vector3 {
+        self.face_normal()
+    }
+
+    // The same as for a plane through the rectangle, facing the same way.
+    fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
+        planar_texture_coords(&self.origin, &-self.face_normal(), hit_point)
+    }
 }
 
 impl AABB {
@@ -238,8 +665,9 @@ impl AABB {
     }
 }
 
-impl Intersectable for AABB {
-    fn intersect(&self, ray: &Ray) -> Option<f64> {
+impl AABB {
+    // Distances along the ray to where it enters and exits the box, wherever the ray starts.
+    fn slab_distances(&self, ray: &Ray) -> Option<(f64, f64)> {
         let mut tmin = (self.bounds[ray.x_sign()].x - ray.origin.x) * ray.inverted_direction.x;
         let mut tmax = (self.bounds[1 - ray.x_sign()].x - ray.origin.x) * ray.inverted_direction.x;
 
@@ -272,6 +700,14 @@ impl Intersectable for AABB {
             tmax = tzmax;
         }
 
+        Some((tmin, tmax))
+    }
+}
+
+impl Intersectable for AABB {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (tmin, tmax) = self.slab_distances(ray)?;
+
         if tmin >= 0.0 {
             Some(tmin)
         } else if tmax >= 0.0 {
@@ -327,51 +763,408 @@ impl Intersectable for AABB {
         }
     }
 
-    fn texture_coords(&self, _hit_point: &Point3) -> TextureCoords {
-        // TODO: Can we calculate this somehow?
-        TextureCoords { x: 0.0, y: 0.0 }
+    fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
+        let position = Vector3::new((hit_point.x - self.bounds[0].x) / self.width_x(),
+                                    (hit_point.y - self.bounds[0].y) / self.width_y(),
+                                    (hit_point.z - self.bounds[0].z) / self.width_z());
+        box_texture_coords(self.texture_layout, &self.surface_normal(hit_point), &position)
     }
 }
 
-impl Intersectable for Body {
+impl Solid for AABB {
+    fn span_distances(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        self.slab_distances(ray).into_iter().collect()
+    }
+}
+
+impl Triangle {
+    pub fn face_normal(&self) -> Vector3 {
+        triangle_face_normal(&self.vertices)
+    }
+
+    pub fn barycentric_coords(&self, point: &Point3) -> [f64; 3] {
+        triangle_barycentric_coords(&self.vertices, point)
+    }
+}
+
+impl Intersectable for Triangle {
     fn intersect(&self, ray: &Ray) -> Option<f64> {
-        match *self {
-            Body::Sphere(ref sphere) => sphere.intersect(ray),
-            Body::Plane(ref plane) => plane.intersect(ray),
-            Body::Disk(ref disk) => disk.intersect(ray),
-            Body::AABB(ref aabb) => aabb.intersect(ray),
-        }
+        intersect_triangle(&self.vertices, ray)
     }
 
     fn surface_normal(&self, hit_point: &Point3) -> Vector3 {
-        match *self {
-            Body::Sphere(ref sphere) => sphere.surface_normal(hit_point),
-            Body::Plane(ref plane) => plane.surface_normal(hit_point),
-            Body::Disk(ref disk) => disk.surface_normal(hit_point),
-            Body::AABB(ref aabb) => aabb.surface_normal(hit_point),
-        }
+        triangle_surface_normal(&self.vertices, &self.normals, hit_point)
     }
 
     fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
-        match *self {
-            Body::Sphere(ref sphere) => sphere.texture_coords(hit_point),
-            Body::Plane(ref plane) => plane.texture_coords(hit_point),
-            Body::Disk(ref disk) => disk.texture_coords(hit_point),
-            Body::AABB(ref aabb) => aabb.texture_coords(hit_point),
+        triangle_texture_coords(&self.vertices, &self.texture_coords, hit_point)
+    }
+}
+
+// The triangle functions work on plain vertices, so that meshes can share them without storing a
+// material for every triangle.
+
+pub fn intersect_triangle(vertices: &[Point3; 3], ray: &Ray) -> Option<f64> {
+    // Möller–Trumbore: solves origin + t * direction = v0 + u * edge1 + v * edge2 for t, u and v
+    // using Cramer's rule. Both sides of the triangle can be hit.
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+
+    // The ray is parallel to the triangle
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+
+    let to_origin = ray.origin - vertices[0];
+    let u = to_origin.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = to_origin.cross(edge1);
+    let v = ray.direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge2.dot(q) * inverse_determinant;
+    if distance > 1e-9 { Some(distance) } else { None }
+}
+
+pub fn triangle_face_normal(vertices: &[Point3; 3]) -> Vector3 {
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+    edge1.cross(edge2).normalize()
+}
+
+// Weights of the three vertices for a point on the triangle. They are all positive inside of the
+// triangle, and always sum up to one.
+pub fn triangle_barycentric_coords(vertices: &[Point3; 3], point: &Point3) -> [f64; 3] {
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+    let to_point = point - vertices[0];
+
+    let d11 = edge1.dot(edge1);
+    let d12 = edge1.dot(edge2);
+    let d22 = edge2.dot(edge2);
+    let dp1 = to_point.dot(edge1);
+    let dp2 = to_point.dot(edge2);
+
+    let denominator = d11 * d22 - d12 * d12;
+    if denominator.abs() < 1e-20 {
+        // Degenerate triangle without any area
+        return [1.0, 0.0, 0.0];
+    }
+
+    let v = (d22 * dp1 - d12 * dp2) / denominator;
+    let w = (d11 * dp2 - d12 * dp1) / denominator;
+    [1.0 - v - w, v, w]
+}
+
+pub fn triangle_surface_normal(vertices: &[Point3; 3],
+                               normals: &Option<[Vector3; 3]>,
+                               hit_point: &Point3)
+                               -> Vector3 {
+    match *normals {
+        Some(ref normals) => {
+            let weights = triangle_barycentric_coords(vertices, hit_point);
+            let normal = normals[0] * weights[0] + normals[1] * weights[1] +
+                         normals[2] * weights[2];
+
+            if normal.magnitude2() > 0.0 {
+                normal.normalize()
+            } else {
+                triangle_face_normal(vertices)
+            }
+        }
+        None => triangle_face_normal(vertices),
+    }
+}
+
+pub fn triangle_texture_coords(vertices: &[Point3; 3],
+                               texture_coords: &Option<[TextureCoords; 3]>,
+                               hit_point: &Point3)
+                               -> TextureCoords {
+    let weights = triangle_barycentric_coords(vertices, hit_point);
+    match *texture_coords {
+        Some(ref coords) => {
+            TextureCoords {
+                x: (coords[0].x as f64 * weights[0] + coords[1].x as f64 * weights[1] +
+                    coords[2].x as f64 * weights[2]) as f32,
+                y: (coords[0].y as f64 * weights[0] + coords[1].y as f64 * weights[1] +
+                    coords[2].y as f64 * weights[2]) as f32,
+            }
+        }
+        None => {
+            TextureCoords {
+                x: weights[1] as f32,
+                y: weights[2] as f32,
+            }
         }
     }
 }
 
+fn hit_details<'a, T>(body: &T,
+                      ray: &Ray,
+                      material: &'a Material)
+                      -> Option<(f64, Vector3, TextureCoords, &'a Material)>
+    where T: Intersectable
+{
+    body.intersect(ray).map(|distance| {
+        let hit_point = ray.origin + (ray.direction * distance);
+        (distance, body.surface_normal(&hit_point), body.texture_coords(&hit_point), material)
+    })
+}
+
+// Texture coordinates on a plane, in the same units as positions. The normal points away from the
+// side that is seen, like the normals of planes and disks do.
+pub fn planar_texture_coords(origin: &Point3,
+                             normal: &Vector3,
+                             hit_point: &Point3)
+                             -> TextureCoords {
+    let mut x_axis = normal.cross(Vector3::unit_z());
+
+    if x_axis.magnitude2() == 0.0 {
+        x_axis = normal.cross(Vector3::unit_y());
+    }
+
+    let y_axis = normal.cross(x_axis);
+
+    let hit_vec = hit_point - origin;
+    TextureCoords {
+        x: hit_vec.dot(x_axis) as f32,
+        y: hit_vec.dot(y_axis) as f32,
+    }
+}
+
+// Texture coordinates on the face of a box with the given normal. The position is where the point
+// is in the box, from 0 to 1 along each axis.
+pub fn box_texture_coords(layout: BoxTextureLayout,
+                          normal: &Vector3,
+                          position: &Vector3)
+                          -> TextureCoords {
+    // The face's place in the cross, and where the point is on the face as seen from outside of
+    // the box. Up is +Y on the sides, and towards the back on the top and the bottom.
+    let (column, row, x, y) = if normal.x > 0.5 {
+        (2.0, 1.0, 1.0 - position.z, 1.0 - position.y)
+    } else if normal.x < -0.5 {
+        (0.0, 1.0, position.z, 1.0 - position.y)
+    } else if normal.y > 0.5 {
+        (1.0, 0.0, position.x, position.z)
+    } else if normal.y < -0.5 {
+        (1.0, 2.0, position.x, 1.0 - position.z)
+    } else if normal.z > 0.5 {
+        (1.0, 1.0, position.x, 1.0 - position.y)
+    } else {
+        (3.0, 1.0, 1.0 - position.x, 1.0 - position.y)
+    };
+
+    match layout {
+        BoxTextureLayout::Repeated => {
+            TextureCoords {
+                x: x as f32,
+                y: y as f32,
+            }
+        }
+        BoxTextureLayout::Cross => {
+            TextureCoords {
+                x: ((column + x) / 4.0) as f32,
+                y: ((row + y) / 3.0) as f32,
+            }
+        }
+    }
+}
+
+fn solid_spans<'a, T>(body: &'a Body,
+                      solid: &T,
+                      ray: &Ray,
+                      material: &'a Material)
+                      -> Vec<Span<'a>>
+    where T: Solid
+{
+    let boundary = |distance: f64| {
+        let hit_point = ray.origin + (ray.direction * distance);
+        Intersection {
+            distance,
+            body,
+            normal: solid.surface_normal(&hit_point),
+            texture_coords: solid.texture_coords(&hit_point),
+            material,
+        }
+    };
+
+    solid
+        .span_distances(ray)
+        .into_iter()
+        .map(|(entry, exit)| {
+                 Span {
+                     entry: boundary(entry),
+                     exit: boundary(exit),
+                 }
+             })
+        .collect()
+}
+
+// Moves an intersection found with a ray in the transform's object space back out to world space.
+fn to_world_space<'a>(intersection: Intersection<'a>,
+                      transform: &Transform,
+                      scale: f64)
+                      -> Intersection<'a> {
+    Intersection {
+        distance: intersection.distance / scale,
+        normal: transform.transform_normal(intersection.normal),
+        ..intersection
+    }
+}
+
+// Where a ray hit a body, and what the surface looks like at that point.
 pub struct Intersection<'a> {
     pub distance: f64,
+    // The body in the scene that was hit, even when it is made up of many parts.
     pub body: &'a Body,
+    pub normal: Vector3,
+    pub texture_coords: TextureCoords,
+    pub material: &'a Material,
 }
 
-impl<'a> Intersection<'a> {
-    pub fn new<'b>(distance: f64, body: &'b Body) -> Intersection<'b> {
-        Intersection {
-            distance: distance,
-            body: body,
-        }
+// Part of a ray that is inside of a solid body. The normals of both ends point out of the body.
+pub struct Span<'a> {
+    pub entry: Intersection<'a>,
+    pub exit: Intersection<'a>,
+}
+
+#[cfg(test)]
+fn test_triangle() -> Triangle {
+    Triangle {
+        vertices: [Point3::new(0.0, 0.0, -1.0),
+                   Point3::new(1.0, 0.0, -1.0),
+                   Point3::new(0.0, 1.0, -1.0)],
+        normals: None,
+        texture_coords: None,
+        transform: None,
+        material: test_material(),
     }
 }
+
+#[test]
+fn it_intersects_triangles_from_both_sides() {
+    let triangle = test_triangle();
+
+    let ray = Ray::new(Point3::new(0.25, 0.25, 0.0), -Vector3::unit_z());
+    assert_eq!(triangle.intersect(&ray), Some(1.0));
+
+    let ray = Ray::new(Point3::new(0.25, 0.25, -2.0), Vector3::unit_z());
+    assert_eq!(triangle.intersect(&ray), Some(1.0));
+
+    let ray = Ray::new(Point3::new(0.75, 0.75, 0.0), -Vector3::unit_z());
+    assert_eq!(triangle.intersect(&ray), None);
+
+    let ray = Ray::new(Point3::new(0.25, 0.25, 0.0), Vector3::unit_x());
+    assert_eq!(triangle.intersect(&ray), None);
+}
+
+#[test]
+fn it_interpolates_triangle_normals_and_texture_coords() {
+    let mut triangle = test_triangle();
+    let hit_point = Point3::new(0.5, 0.0, -1.0);
+    assert_eq!(triangle.surface_normal(&hit_point), Vector3::unit_z());
+
+    triangle.normals = Some([Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()]);
+    triangle.texture_coords = Some([TextureCoords { x: 0.0, y: 0.0 },
+                                    TextureCoords { x: 1.0, y: 0.0 },
+                                    TextureCoords { x: 0.0, y: 2.0 }]);
+
+    let normal = triangle.surface_normal(&hit_point);
+    assert!((normal - Vector3::new(1.0, 1.0, 0.0).normalize()).magnitude() < 1e-9);
+
+    let coords = triangle.texture_coords(&Point3::new(0.25, 0.5, -1.0));
+    assert_eq!((coords.x, coords.y), (0.25, 1.0));
+}
+
+#[test]
+fn it_intersects_transformed_bodies() {
+    let mut triangle = test_triangle();
+    triangle.transform = Transform::new(Vector3::new(0.0, 0.0, -1.0),
+                                        Vector3::new(0.0, 90.0, 0.0),
+                                        Vector3::new(2.0, 2.0, 2.0));
+    let body = Body::Triangle(triangle);
+
+    // The triangle is turned to face +X, and its vertices are twice as far from the origin.
+    let ray = Ray::new(Point3::new(10.0, 0.5, -1.5), -Vector3::unit_x());
+    let intersection = body.intersection(&ray).unwrap();
+    assert!((intersection.distance - 12.0).abs() < 1e-9);
+    assert!((intersection.normal - Vector3::unit_x()).magnitude() < 1e-9);
+
+    let ray = Ray::new(Point3::new(10.0, 0.5, 0.5), -Vector3::unit_x());
+    assert!(body.intersection(&ray).is_none());
+}
+
+#[test]
+fn it_maps_textures_to_box_faces() {
+    let mut aabb = AABB {
+        bounds: [Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)],
+        texture_layout: BoxTextureLayout::Repeated,
+        material: test_material(),
+        transform: None,
+    };
+    let coords = |aabb: &AABB, point: Point3| {
+        let coords = aabb.texture_coords(&point);
+        (coords.x, coords.y)
+    };
+
+    // Towards the top left of the front, and the middle of the right side.
+    assert_eq!(coords(&aabb, Point3::new(-0.5, 0.5, 1.0)), (0.25, 0.25));
+    assert_eq!(coords(&aabb, Point3::new(1.0, 0.0, 0.0)), (0.5, 0.5));
+
+    aabb.texture_layout = BoxTextureLayout::Cross;
+    assert_eq!(coords(&aabb, Point3::new(1.0, 0.0, 0.0)), (0.625, 0.5));
+    // The top's front edge meets the front's top edge.
+    assert_eq!(coords(&aabb, Point3::new(0.0, 1.0, 1.0)), (0.375, 1.0 / 3.0));
+    assert_eq!(coords(&aabb, Point3::new(0.0, 0.0, -1.0)), (0.875, 0.5));
+}
+
+#[test]
+fn it_intersects_rectangles_and_rings() {
+    let rectangle = Rectangle {
+        origin: Point3::new(-1.0, -1.0, -5.0),
+        edges: [Vector3::new(2.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 0.0)],
+        material: test_material(),
+        transform: None,
+    };
+
+    // The edges make a parallelogram leaning to the right.
+    let ray = Ray::new(Point3::new(0.9, -0.5, 0.0), -Vector3::unit_z());
+    assert_eq!(rectangle.intersect(&ray), Some(5.0));
+    let ray = Ray::new(Point3::new(-0.9, -0.5, 0.0), -Vector3::unit_z());
+    assert_eq!(rectangle.intersect(&ray), None);
+    assert_eq!(rectangle.surface_normal(&Point3::new(0.0, -0.5, -5.0)),
+               Vector3::unit_z());
+
+    let plane = Plane {
+        origin: rectangle.origin,
+        normal: -Vector3::unit_z(),
+        material: test_material(),
+        transform: None,
+    };
+    let hit_point = Point3::new(0.5, -0.5, -5.0);
+    let (coords, plane_coords) = (rectangle.texture_coords(&hit_point),
+                                  plane.texture_coords(&hit_point));
+    assert_eq!((coords.x, coords.y), (plane_coords.x, plane_coords.y));
+
+    let ring = Disk {
+        origin: Point3::new(0.0, 0.0, -5.0),
+        normal: -Vector3::unit_z(),
+        radius: 1.0,
+        inner_radius: 0.5,
+        material: test_material(),
+        transform: None,
+    };
+    let ray = Ray::new(Point3::new(0.75, 0.0, 0.0), -Vector3::unit_z());
+    assert_eq!(ring.intersect(&ray), Some(5.0));
+    let ray = Ray::new(Point3::new(0.25, 0.0, 0.0), -Vector3::unit_z());
+    assert_eq!(ring.intersect(&ray), None);
+}
@@ -0,0 +1,462 @@
+use serde;
+
+use bodies::{box_texture_coords, BoxTextureLayout, Intersectable, Solid};
+use cgmath::prelude::*;
+use material::{Material, TextureCoords};
+use ray::Ray;
+use roots::solve_quadratic;
+use transform::Transform;
+use super::{Point3, Vector3};
+
+fn zero_rotation() -> Vector3 {
+    Vector3::zero()
+}
+
+// A box that can be turned any way around its center. Unlike with AABB, the size of the box is
+// given by how far it reaches from the center along each of its own axes.
+#[derive(Debug, Clone)]
+pub struct OrientedBox {
+    pub half_extents: Vector3,
+    pub texture_layout: BoxTextureLayout,
+    pub material: Material,
+    // Places the box's own space, where it is centered on the origin and lines up with the axes.
+    // The center and rotation from the scene file are combined with its transform into this one
+    // when the box is loaded.
+    pub transform: Option<Transform>,
+}
+
+// How an oriented box is described in the scene file.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrientedBoxDefinition {
+    center: Point3,
+    half_extents: Vector3,
+    // Degrees around the X, Y and Z axes, in that order like in transforms.
+    #[serde(default = "zero_rotation")]
+    rotation: Vector3,
+    #[serde(default)]
+    texture_layout: BoxTextureLayout,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+// An oriented box with its edges and corners rounded off with the radius. It still reaches as far
+// as the half extents.
+#[derive(Debug, Clone)]
+pub struct RoundedBox {
+    pub half_extents: Vector3,
+    pub radius: f64,
+    pub texture_layout: BoxTextureLayout,
+    pub material: Material,
+    pub transform: Option<Transform>,
+}
+
+// How a rounded box is described in the scene file.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RoundedBoxDefinition {
+    center: Point3,
+    half_extents: Vector3,
+    radius: f64,
+    #[serde(default = "zero_rotation")]
+    rotation: Vector3,
+    #[serde(default)]
+    texture_layout: BoxTextureLayout,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+// Moves the box's own space to the center and turns it by the rotation, and then places it with
+// the transform from the scene file.
+fn placement(center: Point3, rotation: Vector3, transform: Option<Transform>) -> Transform {
+    let placement = Transform::new(center.to_vec(), rotation, Vector3::new(1.0, 1.0, 1.0))
+        .expect("Rotations can always be inverted");
+
+    match transform {
+        Some(transform) => transform.compose(&placement),
+        None => placement,
+    }
+}
+
+// Boxes need to reach out from the center on every axis, or their faces have no size to place
+// points on them by.
+fn check_half_extents(half_extents: Vector3) -> Result<(), String> {
+    if half_extents.x > 0.0 && half_extents.y > 0.0 && half_extents.z > 0.0 {
+        Ok(())
+    } else {
+        Err(String::from("The half extents of a box must all be above zero"))
+    }
+}
+
+fn check_radius(radius: f64) -> Result<(), String> {
+    if radius >= 0.0 {
+        Ok(())
+    } else {
+        Err(String::from("The radius of a rounded box can't be negative"))
+    }
+}
+
+// Distances along the ray to where it enters and exits a box around the origin, wherever the ray
+// starts.
+pub fn slab_distances(half_extents: &Vector3, ray: &Ray) -> Option<(f64, f64)> {
+    let mut near = f64::NEG_INFINITY;
+    let mut far = f64::INFINITY;
+
+    for axis in 0..3 {
+        let to_min = (-half_extents[axis] - ray.origin[axis]) * ray.inverted_direction[axis];
+        let to_max = (half_extents[axis] - ray.origin[axis]) * ray.inverted_direction[axis];
+        near = near.max(to_min.min(to_max));
+        far = far.min(to_min.max(to_max));
+    }
+
+    if near > far { None } else { Some((near, far)) }
+}
+
+// The normal of the face that a point on a box around the origin is on. Which face is decided by
+// how close the point is to each face compared to the size of the box.
+fn face_normal(half_extents: &Vector3, point: &Point3) -> Vector3 {
+    let mut axis = 0;
+    for i in 1..3 {
+        if (point[i] / half_extents[i]).abs() > (point[axis] / half_extents[axis]).abs() {
+            axis = i;
+        }
+    }
+
+    let mut normal = Vector3::zero();
+    normal[axis] = point[axis].signum();
+    normal
+}
+
+fn face_texture_coords(layout: BoxTextureLayout,
+                       half_extents: &Vector3,
+                       point: &Point3)
+                       -> TextureCoords {
+    let position = |axis: usize| {
+        ((point[axis] / half_extents[axis] + 1.0) / 2.0).clamp(0.0, 1.0)
+    };
+
+    box_texture_coords(layout,
+                       &face_normal(half_extents, point),
+                       &Vector3::new(position(0), position(1), position(2)))
+}
+
+// Distances along the ray to where it enters and exits a sphere.
+fn sphere_distances(center: &Point3, radius: f64, ray: &Ray) -> Option<(f64, f64)> {
+    let offset = ray.origin - center;
+    let roots = solve_quadratic(ray.direction.magnitude2(),
+                                2.0 * offset.dot(ray.direction),
+                                offset.magnitude2() - radius * radius);
+    if roots.len() == 2 { Some((roots[0], roots[1])) } else { None }
+}
+
+// Distances along the ray to where it enters and exits a cylinder along one of the axes, reaching
+// half_length from the center to either end.
+fn cylinder_distances(axis: usize,
+                      center: &Point3,
+                      half_length: f64,
+                      radius: f64,
+                      ray: &Ray)
+                      -> Option<(f64, f64)> {
+    // Across the axis the cylinder is a circle.
+    let across = [(axis + 1) % 3, (axis + 2) % 3];
+    let offset = ray.origin - center;
+    let (mut a, mut b, mut c) = (0.0, 0.0, -radius * radius);
+    for i in across {
+        a += ray.direction[i] * ray.direction[i];
+        b += 2.0 * offset[i] * ray.direction[i];
+        c += offset[i] * offset[i];
+    }
+
+    let (entry, exit) = if a < 1e-12 {
+        // Along the axis, the ray is either always inside of the circle or never.
+        if c > 0.0 {
+            return None;
+        }
+        (f64::NEG_INFINITY, f64::INFINITY)
+    } else {
+        let roots = solve_quadratic(a, b, c);
+        if roots.len() < 2 {
+            return None;
+        }
+        (roots[0], roots[1])
+    };
+
+    let mut ends = Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    ends[axis] = half_length;
+    let (near, far) = slab_distances(&ends, &Ray::new(Point3::from_vec(offset), ray.direction))?;
+
+    let (entry, exit) = (entry.max(near), exit.min(far));
+    if entry > exit { None } else { Some((entry, exit)) }
+}
+
+// First distance that isn't behind the ray, like intersect returns.
+fn first_in_front(entry: f64, exit: f64) -> Option<f64> {
+    if entry >= 0.0 {
+        Some(entry)
+    } else if exit >= 0.0 {
+        Some(exit)
+    } else {
+        None
+    }
+}
+
+impl Intersectable for OrientedBox {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        slab_distances(&self.half_extents, ray)
+            .and_then(|(entry, exit)| first_in_front(entry, exit))
+    }
+
+    fn surface_normal(&self, hit_point: &Point3) -> Vector3 {
+        face_normal(&self.half_extents, hit_point)
+    }
+
+    fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
+        face_texture_coords(self.texture_layout, &self.half_extents, hit_point)
+    }
+}
+
+impl Solid for OrientedBox {
+    fn span_distances(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        slab_distances(&self.half_extents, ray).into_iter().collect()
+    }
+}
+
+impl RoundedBox {
+    // The radius can't be larger than the box.
+    fn radius(&self) -> f64 {
+        self.radius
+            .min(self.half_extents.x)
+            .min(self.half_extents.y)
+            .min(self.half_extents.z)
+            .max(0.0)
+    }
+
+    // How far a point in the box's own space is from the rounded box after the rounded edges have
+    // been taken off of each side. Negative components are inside of the smaller box.
+    fn inner_offset(&self, point: &Point3) -> Vector3 {
+        let inner = self.half_extents - Vector3::new(1.0, 1.0, 1.0) * self.radius();
+        Vector3::new(point.x.abs() - inner.x,
+                     point.y.abs() - inner.y,
+                     point.z.abs() - inner.z)
+    }
+
+    // Distances along the ray in the box's own space to where it enters and exits the rounded box.
+    // The rounded box is made up of three boxes that each reach the faces on one axis, a cylinder
+    // along every edge and a sphere on every corner. It is convex, so the ray is inside of it from
+    // where it enters the first of those parts until it exits the last.
+    fn span(&self, ray: &Ray) -> Option<(f64, f64)> {
+        // Rays that miss the box without rounding miss all of its parts too.
+        slab_distances(&self.half_extents, ray)?;
+
+        let radius = self.radius();
+        let inner = self.half_extents - Vector3::new(1.0, 1.0, 1.0) * radius;
+        let mut parts = Vec::with_capacity(3 + 12 + 8);
+
+        for axis in 0..3 {
+            let mut half_extents = inner;
+            half_extents[axis] = self.half_extents[axis];
+            parts.push(slab_distances(&half_extents, ray));
+        }
+
+        for x in [-1.0, 1.0] {
+            for y in [-1.0, 1.0] {
+                for z in [-1.0, 1.0] {
+                    let corner = Point3::new(x * inner.x, y * inner.y, z * inner.z);
+                    parts.push(sphere_distances(&corner, radius, ray));
+
+                    // Each edge between this corner and one on the other side of the box.
+                    for axis in 0..3 {
+                        if corner[axis] >= 0.0 {
+                            let mut center = corner;
+                            center[axis] = 0.0;
+                            parts.push(cylinder_distances(axis, &center, inner[axis], radius, ray));
+                        }
+                    }
+                }
+            }
+        }
+
+        parts
+            .into_iter()
+            .flatten()
+            .fold(None, |span, (entry, exit)| match span {
+                Some((first_entry, last_exit)) => {
+                    Some((entry.min(first_entry), exit.max(last_exit)))
+                }
+                None => Some((entry, exit)),
+            })
+    }
+}
+
+impl Intersectable for RoundedBox {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.span(ray)
+            .and_then(|(entry, exit)| first_in_front(entry, exit))
+    }
+
+    fn surface_normal(&self, hit_point: &Point3) -> Vector3 {
+        let offset = self.inner_offset(hit_point);
+
+        if offset.x > 0.0 || offset.y > 0.0 || offset.z > 0.0 {
+            // On a rounded edge or corner, pointing away from the closest point on the inner box.
+            Vector3::new(offset.x.max(0.0) * hit_point.x.signum(),
+                         offset.y.max(0.0) * hit_point.y.signum(),
+                         offset.z.max(0.0) * hit_point.z.signum())
+                    .normalize()
+        } else {
+            face_normal(&self.half_extents, hit_point)
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
+        face_texture_coords(self.texture_layout, &self.half_extents, hit_point)
+    }
+}
+
+impl Solid for RoundedBox {
+    fn span_distances(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        self.span(ray).into_iter().collect()
+    }
+}
+
+impl serde::Deserialize for OrientedBox {
+    fn deserialize<D>(deserializer: D) -> Result<OrientedBox, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let OrientedBoxDefinition {
+            center,
+            half_extents,
+            rotation,
+            texture_layout,
+            material,
+            transform,
+        } = OrientedBoxDefinition::deserialize(deserializer)?;
+        check_half_extents(half_extents).map_err(Error::custom)?;
+
+        Ok(OrientedBox {
+               half_extents,
+               texture_layout,
+               material,
+               transform: Some(placement(center, rotation, transform)),
+           })
+    }
+}
+
+impl serde::Deserialize for RoundedBox {
+    fn deserialize<D>(deserializer: D) -> Result<RoundedBox, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let RoundedBoxDefinition {
+            center,
+            half_extents,
+            radius,
+            rotation,
+            texture_layout,
+            material,
+            transform,
+        } = RoundedBoxDefinition::deserialize(deserializer)?;
+        check_half_extents(half_extents).map_err(Error::custom)?;
+        check_radius(radius).map_err(Error::custom)?;
+
+        Ok(RoundedBox {
+               half_extents,
+               radius,
+               texture_layout,
+               material,
+               transform: Some(placement(center, rotation, transform)),
+           })
+    }
+}
+
+#[test]
+fn it_intersects_oriented_boxes() {
+    use bodies::Body;
+    use material::test_material;
+
+    // Turned by 45 degrees so that an edge points at the origin.
+    let oriented_box = Body::OrientedBox(OrientedBox {
+        half_extents: Vector3::new(1.0, 1.0, 1.0),
+        texture_layout: BoxTextureLayout::Repeated,
+        material: test_material(),
+        transform: Some(placement(Point3::new(0.0, 0.0, -5.0),
+                                  Vector3::new(0.0, 45.0, 0.0),
+                                  None)),
+    });
+
+    let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), -Vector3::unit_z());
+    let distance = oriented_box.intersection(&ray).unwrap().distance;
+    assert!((distance - (5.0 - 2.0f64.sqrt())).abs() < 1e-9);
+
+    let ray = Ray::new(Point3::new(-0.5, 0.0, 0.0), -Vector3::unit_z());
+    let normal = oriented_box.intersection(&ray).unwrap().normal;
+    assert!((normal - Vector3::new(-1.0, 0.0, 1.0).normalize()).magnitude() < 1e-9);
+}
+
+#[test]
+fn it_places_boxes_before_transforming_them() {
+    // Centered to the right of the origin, and then turned a quarter around the Y axis and scaled
+    // by the transform so that it ends up in front of the origin.
+    let transform = Transform::new(Vector3::zero(),
+                                   Vector3::new(0.0, 90.0, 0.0),
+                                   Vector3::new(2.0, 2.0, 2.0));
+    let placement = placement(Point3::new(2.5, 0.0, 0.0), Vector3::zero(), transform);
+
+    let center = placement.transform_point(Point3::new(0.0, 0.0, 0.0));
+    assert!((center - Point3::new(0.0, 0.0, -5.0)).magnitude() < 1e-9);
+}
+
+#[test]
+fn it_intersects_rounded_boxes() {
+    use bodies::Body;
+    use material::test_material;
+
+    let rounded_box = Body::RoundedBox(RoundedBox {
+        half_extents: Vector3::new(2.0, 1.0, 1.0),
+        radius: 0.5,
+        texture_layout: BoxTextureLayout::Repeated,
+        material: test_material(),
+        transform: Some(placement(Point3::new(0.0, 0.0, -5.0), Vector3::zero(), None)),
+    });
+
+    // Straight onto the front face, and through the whole box.
+    let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), -Vector3::unit_z());
+    let spans = rounded_box.spans(&ray);
+    assert!((spans[0].entry.distance - 4.0).abs() < 1e-6);
+    assert!((spans[0].exit.distance - 6.0).abs() < 1e-6);
+    assert!((spans[0].entry.normal - Vector3::unit_z()).magnitude() < 1e-9);
+
+    // Diagonally onto the rounded edge between the front and the top.
+    let ray = Ray::new(Point3::new(0.0, 5.0, 0.0),
+                       Vector3::new(0.0, -1.0, -1.0).normalize());
+    let intersection = rounded_box.intersection(&ray).unwrap();
+    let hit_point = ray.origin + ray.direction * intersection.distance;
+    let edge_point = Point3::new(0.0, 0.5, -4.5) + Vector3::new(0.0, 0.5, 0.5).normalize() * 0.5;
+    assert!((hit_point - edge_point).magnitude() < 1e-6);
+    let normal = intersection.normal;
+    assert!((normal - Vector3::new(0.0, 1.0, 1.0).normalize()).magnitude() < 1e-6);
+
+    // Straight at a rounded corner, along the diagonal of the box.
+    let diagonal = Vector3::new(1.0, 1.0, 1.0).normalize();
+    let ray = Ray::new(Point3::new(1.5, 0.5, -4.5) + diagonal * 10.0, -diagonal);
+    assert!((rounded_box.intersection(&ray).unwrap().distance - 9.5).abs() < 1e-9);
+
+    // Past the rounded corner, where the box without rounding would have been hit.
+    let ray = Ray::new(Point3::new(1.95, 0.95, 0.0), -Vector3::unit_z());
+    assert!(rounded_box.intersection(&ray).is_none());
+}
+
+#[test]
+fn it_rejects_boxes_without_a_size() {
+    assert!(check_half_extents(Vector3::new(1.0, 0.0, 1.0)).is_err());
+    assert!(check_half_extents(Vector3::new(1.0, 1.0, -1.0)).is_err());
+    assert!(check_half_extents(Vector3::new(1.0, 2.0, 3.0)).is_ok());
+
+    assert!(check_radius(-0.5).is_err());
+    assert!(check_radius(0.0).is_ok());
+}
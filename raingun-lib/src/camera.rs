@@ -0,0 +1,523 @@
+use ray::Ray;
+use stereo::{Eye, Stereo};
+use super::{Point3, Vector3};
+use cgmath::prelude::*;
+use rand;
+use serde;
+
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub position: Point3,
+    pub look_at: Point3,
+    pub up: Vector3,
+    pub fov: f64,
+    pub fov_axis: FovAxis,
+    pub projection: Projection,
+
+    // Thin lens parameters. An aperture radius of zero gives a pinhole camera where everything
+    // is in focus. The focal distance defaults to the distance to the look-at point. Panoramic
+    // projections are always rendered as pinhole cameras.
+    pub aperture_radius: f64,
+    pub focal_distance: Option<f64>,
+    // Number of aperture blades, for polygonal bokeh. Anything below 3 gives a round aperture.
+    pub aperture_blades: u32,
+    // Rotation of the aperture polygon, in degrees.
+    pub aperture_rotation: f64,
+
+    // Renders one image per eye when set. See Camera::eye.
+    pub stereo: Option<Stereo>,
+
+    // Horizontal offset of the sensor, used to make the eyes of a stereo camera converge.
+    sensor_shift: f64,
+}
+
+// How a camera is described in the scene file. Everything but the sensor shift can be set.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default, rename_all = "camelCase")]
+struct CameraDefinition {
+    position: Point3,
+    look_at: Point3,
+    up: Vector3,
+    fov: f64,
+    fov_axis: FovAxis,
+    projection: Projection,
+    aperture_radius: f64,
+    focal_distance: Option<f64>,
+    aperture_blades: u32,
+    aperture_rotation: f64,
+    stereo: Option<Stereo>,
+}
+
+// Which axis of the image the camera's field of view spans. The other axis is derived from the
+// image's aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum FovAxis {
+    Horizontal,
+    Vertical,
+    Diagonal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Projection {
+    // Rays fan out from the camera position, covering the camera's field of view.
+    Perspective,
+    // Rays are parallel to the view direction, starting on a plane through the camera position
+    // that is view_width world units wide. The height follows from the image's aspect ratio.
+    Orthographic { view_width: f64 },
+    // Covers every direction around the camera, with longitude along the X axis and latitude
+    // along the Y axis. Use an image twice as wide as it is high to avoid stretching.
+    Equirectangular,
+    // Equidistant fisheye: the distance from the center of the image is proportional to the angle
+    // away from the view direction. The image circle fills the shorter side of the image and
+    // covers fov degrees (up to 360) across; pixels outside of it are not rendered.
+    Fisheye { fov: f64 },
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        // Same viewpoint as the old hard-coded camera: at the origin, looking down -Z.
+        Camera {
+            position: Point3::origin(),
+            look_at: Point3::new(0.0, 0.0, -1.0),
+            up: Vector3::unit_y(),
+            fov: 90.0,
+            fov_axis: FovAxis::Vertical,
+            projection: Projection::Perspective,
+            aperture_radius: 0.0,
+            focal_distance: None,
+            aperture_blades: 0,
+            aperture_rotation: 0.0,
+            stereo: None,
+            sensor_shift: 0.0,
+        }
+    }
+}
+
+impl Default for CameraDefinition {
+    fn default() -> CameraDefinition {
+        let Camera {
+            position,
+            look_at,
+            up,
+            fov,
+            fov_axis,
+            projection,
+            aperture_radius,
+            focal_distance,
+            aperture_blades,
+            aperture_rotation,
+            stereo,
+            ..
+        } = Camera::default();
+
+        CameraDefinition {
+            position,
+            look_at,
+            up,
+            fov,
+            fov_axis,
+            projection,
+            aperture_radius,
+            focal_distance,
+            aperture_blades,
+            aperture_rotation,
+            stereo,
+        }
+    }
+}
+
+// The camera looks from its position towards the look-at point, so they can't be the same point.
+fn check_view_direction(position: Point3, look_at: Point3) -> Result<(), String> {
+    if (look_at - position).magnitude2() > 0.0 {
+        Ok(())
+    } else {
+        Err(String::from("Camera position and lookAt must differ"))
+    }
+}
+
+impl serde::Deserialize for Camera {
+    fn deserialize<D>(deserializer: D) -> Result<Camera, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let CameraDefinition {
+            position,
+            look_at,
+            up,
+            fov,
+            fov_axis,
+            projection,
+            aperture_radius,
+            focal_distance,
+            aperture_blades,
+            aperture_rotation,
+            stereo,
+        } = CameraDefinition::deserialize(deserializer)?;
+        check_view_direction(position, look_at).map_err(Error::custom)?;
+
+        Ok(Camera {
+               position,
+               look_at,
+               up,
+               fov,
+               fov_axis,
+               projection,
+               aperture_radius,
+               focal_distance,
+               aperture_blades,
+               aperture_rotation,
+               stereo,
+               sensor_shift: 0.0,
+           })
+    }
+}
+
+impl Camera {
+    // Returns an orthonormal (right, up, forward) basis for the camera.
+    pub fn basis(&self) -> (Vector3, Vector3, Vector3) {
+        let forward = (self.look_at - self.position).normalize();
+        let mut right = forward.cross(self.up);
+
+        // When looking straight along the up vector there is no sensible "right", so pick any
+        // axis perpendicular to the view direction instead.
+        if right.magnitude2() < 1e-12 {
+            right = forward.cross(Vector3::unit_z());
+            if right.magnitude2() < 1e-12 {
+                right = forward.cross(Vector3::unit_y());
+            }
+        }
+
+        let right = right.normalize();
+        let up = right.cross(forward);
+
+        (right, up, forward)
+    }
+
+    // Returns half the width and half the height of the sensor, placed one unit in front of the
+    // camera, for an image of the given size.
+    pub fn sensor_extent(&self, width: u32, height: u32) -> (f64, f64) {
+        let width = width as f64;
+        let height = height as f64;
+        let half_fov = (self.fov.to_radians() / 2.0).tan();
+
+        match self.fov_axis {
+            FovAxis::Horizontal => (half_fov, half_fov * height / width),
+            FovAxis::Vertical => (half_fov * width / height, half_fov),
+            FovAxis::Diagonal => {
+                let diagonal = (width * width + height * height).sqrt();
+                (half_fov * width / diagonal, half_fov * height / diagonal)
+            }
+        }
+    }
+
+    // Returns the camera for one of the eyes of a stereo camera. Both eyes look in parallel
+    // directions, but their sensors are shifted so they converge at the convergence distance. This
+    // avoids the vertical parallax of toeing in the cameras. The convergence only applies to the
+    // perspective projection; other projections only get their positions offset.
+    //
+    // Cameras without stereo settings are returned unchanged.
+    pub fn eye(&self, eye: Eye) -> Camera {
+        let mut camera = self.clone();
+        camera.stereo = None;
+
+        if let Some(ref stereo) = self.stereo {
+            let (right, _, _) = self.basis();
+            let offset = match eye {
+                Eye::Left => -0.5 * stereo.interocular_distance,
+                Eye::Right => 0.5 * stereo.interocular_distance,
+            };
+
+            camera.position = self.position + right * offset;
+            camera.look_at = self.look_at + right * offset;
+            camera.sensor_shift = -offset / stereo.convergence_distance;
+        }
+
+        camera
+    }
+
+    pub fn focal_distance(&self) -> f64 {
+        self.focal_distance
+            .unwrap_or_else(|| (self.look_at - self.position).magnitude())
+    }
+
+    // Creates a ray through the given position on the image, where pixel (x, y) covers the area
+    // from x to x + 1.0 and y to y + 1.0.
+    //
+    // Returns None for positions that the projection does not cover, e.g. outside of a fisheye's
+    // image circle.
+    pub fn create_prime(&self, x: f64, y: f64, width: u32, height: u32) -> Option<Ray> {
+        // Represent the camera's sensor with -1.0 at 0,0 and 1.0 at width,height.
+        let sensor_x = (x / width as f64) * 2.0 - 1.0;
+        let sensor_y = 1.0 - (y / height as f64) * 2.0;
+
+        let (right, up, forward) = self.basis();
+
+        // Start out with a pinhole ray; the lens is applied afterwards.
+        let (origin, direction) = match self.projection {
+            Projection::Perspective => {
+                // Adjust for aspect ratio and FoV, then send the ray straight into the image
+                // along the forward axis, offset by the sensor position.
+                let (half_width, half_height) = self.sensor_extent(width, height);
+                let direction = forward + right * (sensor_x * half_width + self.sensor_shift) +
+                                up * (sensor_y * half_height);
+                (self.position, direction)
+            }
+            Projection::Orthographic { view_width } => {
+                // Every ray points forward; only the origin moves across the sensor.
+                let half_width = view_width / 2.0;
+                let half_height = half_width * height as f64 / width as f64;
+                let origin = self.position + right * (sensor_x * half_width) +
+                             up * (sensor_y * half_height);
+                (origin, forward)
+            }
+            Projection::Equirectangular => {
+                let longitude = sensor_x * PI;
+                let latitude = sensor_y * PI / 2.0;
+
+                let direction = forward * (latitude.cos() * longitude.cos()) +
+                                right * (latitude.cos() * longitude.sin()) +
+                                up * latitude.sin();
+                return Some(Ray::new(self.position, direction));
+            }
+            Projection::Fisheye { fov } => {
+                // Make the image circle a unit circle in the shorter dimension.
+                let shortest_side = width.min(height) as f64;
+                let circle_x = sensor_x * width as f64 / shortest_side;
+                let circle_y = sensor_y * height as f64 / shortest_side;
+
+                let radius = (circle_x * circle_x + circle_y * circle_y).sqrt();
+                if radius > 1.0 {
+                    return None;
+                }
+
+                let theta = radius * fov.to_radians() / 2.0;
+                let phi = circle_y.atan2(circle_x);
+
+                let direction = forward * theta.cos() +
+                                (right * phi.cos() + up * phi.sin()) * theta.sin();
+                return Some(Ray::new(self.position, direction));
+            }
+        };
+
+        if self.aperture_radius <= 0.0 {
+            return Some(Ray::new(origin, direction.normalize()));
+        }
+
+        // Every ray through the lens converges on the same point on the focal plane as the
+        // pinhole ray, so only things at the focal distance stay sharp. The direction has a
+        // forward component of exactly 1.0, so scaling it gives a point on that plane.
+        let focus_point = origin + direction * self.focal_distance();
+
+        let (lens_u, lens_v) = sample_aperture(rand::random(),
+                                               rand::random(),
+                                               self.aperture_blades,
+                                               self.aperture_rotation.to_radians());
+        let lens_origin = origin + right * (lens_u * self.aperture_radius) +
+                          up * (lens_v * self.aperture_radius);
+
+        Some(Ray::new(lens_origin, (focus_point - lens_origin).normalize()))
+    }
+}
+
+// Maps two uniform random numbers in [0, 1) to a uniformly distributed point on the unit aperture.
+// With fewer than three blades the aperture is a circle, otherwise a regular polygon with its
+// corners on the unit circle.
+pub fn sample_aperture(u1: f64, u2: f64, blades: u32, rotation: f64) -> (f64, f64) {
+    if blades < 3 {
+        let radius = u1.sqrt();
+        let angle = 2.0 * PI * u2;
+        return (radius * angle.cos(), radius * angle.sin());
+    }
+
+    // Split the polygon into one triangle per blade, all meeting in the center. As they have equal
+    // areas we pick one of them using u1, and reuse what is left of u1 to sample inside of it.
+    let scaled = u1 * blades as f64;
+    let blade = (scaled.floor() as u32).min(blades - 1);
+    let u1 = scaled - blade as f64;
+
+    let blade_angle = 2.0 * PI / blades as f64;
+    let angle0 = rotation + blade_angle * blade as f64;
+    let angle1 = angle0 + blade_angle;
+
+    // Uniform sampling of the triangle (center, corner0, corner1)
+    let a = u1.sqrt();
+    let weight0 = a * (1.0 - u2);
+    let weight1 = a * u2;
+
+    (weight0 * angle0.cos() + weight1 * angle1.cos(),
+     weight0 * angle0.sin() + weight1 * angle1.sin())
+}
+
+#[test]
+fn it_has_a_default_basis_looking_down_negative_z() {
+    let (right, up, forward) = Camera::default().basis();
+    assert_eq!(right, Vector3::unit_x());
+    assert_eq!(up, Vector3::unit_y());
+    assert_eq!(forward, -Vector3::unit_z());
+}
+
+#[test]
+fn it_rejects_a_look_at_point_at_the_camera_position() {
+    let position = Point3::new(1.0, 2.0, 3.0);
+    assert!(check_view_direction(position, position).is_err());
+    assert!(check_view_direction(position, Point3::new(1.0, 2.0, 2.0)).is_ok());
+}
+
+#[test]
+fn it_builds_a_basis_when_looking_along_up() {
+    let camera = Camera {
+        look_at: Point3::new(0.0, -5.0, 0.0),
+        ..Camera::default()
+    };
+    let (right, up, forward) = camera.basis();
+
+    assert!(right.dot(forward).abs() < 1e-12);
+    assert!(up.dot(forward).abs() < 1e-12);
+    assert!((right.magnitude() - 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn it_applies_fov_to_the_selected_axis() {
+    fn assert_close(actual: (f64, f64), expected: (f64, f64)) {
+        assert!((actual.0 - expected.0).abs() < 1e-12, "{:?} != {:?}", actual, expected);
+        assert!((actual.1 - expected.1).abs() < 1e-12, "{:?} != {:?}", actual, expected);
+    }
+
+    let mut camera = Camera {
+        fov_axis: FovAxis::Vertical,
+        ..Camera::default()
+    };
+    assert_close(camera.sensor_extent(800, 400), (2.0, 1.0));
+    assert_close(camera.sensor_extent(400, 800), (0.5, 1.0));
+
+    camera.fov_axis = FovAxis::Horizontal;
+    assert_close(camera.sensor_extent(800, 400), (1.0, 0.5));
+    assert_close(camera.sensor_extent(400, 800), (1.0, 2.0));
+
+    camera.fov_axis = FovAxis::Diagonal;
+    assert_close(camera.sensor_extent(300, 400), (0.6, 0.8));
+    assert_close(camera.sensor_extent(400, 400), (0.5f64.sqrt(), 0.5f64.sqrt()));
+}
+
+#[test]
+fn it_creates_parallel_rays_for_orthographic_projection() {
+    let camera = Camera {
+        projection: Projection::Orthographic { view_width: 4.0 },
+        ..Camera::default()
+    };
+
+    let top_left = camera.create_prime(0.5, 0.5, 4, 2).unwrap();
+    let bottom_right = camera.create_prime(3.5, 1.5, 4, 2).unwrap();
+
+    assert_eq!(top_left.direction, -Vector3::unit_z());
+    assert_eq!(bottom_right.direction, -Vector3::unit_z());
+    assert_eq!(top_left.origin, Point3::new(-1.5, 0.5, 0.0));
+    assert_eq!(bottom_right.origin, Point3::new(1.5, -0.5, 0.0));
+}
+
+#[test]
+fn it_samples_points_inside_the_aperture() {
+    let steps = 20;
+    for i in 0..steps {
+        for j in 0..steps {
+            let u1 = i as f64 / steps as f64;
+            let u2 = j as f64 / steps as f64;
+
+            let (x, y) = sample_aperture(u1, u2, 0, 0.0);
+            assert!(x * x + y * y <= 1.0 + 1e-12);
+
+            // A hexagon's inscribed circle has a radius of cos(30°), so checking against the
+            // distance to each edge proves the point is inside the hexagon.
+            let (x, y) = sample_aperture(u1, u2, 6, 0.0);
+            let apothem = (PI / 6.0).cos();
+            for edge in 0..6 {
+                let normal_angle = (edge as f64 + 0.5) * PI / 3.0;
+                assert!(x * normal_angle.cos() + y * normal_angle.sin() <= apothem + 1e-12);
+            }
+        }
+    }
+}
+
+#[test]
+fn it_keeps_the_focal_plane_sharp() {
+    let camera = Camera {
+        aperture_radius: 0.5,
+        focal_distance: Some(10.0),
+        ..Camera::default()
+    };
+
+    // The center pixel looks straight ahead, so every lens sample must pass through the point on
+    // the focal plane straight in front of the camera.
+    for _ in 0..10 {
+        let ray = camera.create_prime(1.5, 1.5, 3, 3).unwrap();
+        let distance = -10.0 / ray.direction.z;
+        let focus_point = ray.origin + ray.direction * distance;
+        assert!((focus_point - Point3::new(0.0, 0.0, -10.0)).magnitude() < 1e-9);
+    }
+}
+
+#[test]
+fn it_maps_panoramic_projections_to_directions() {
+    fn assert_direction(ray: Option<Ray>, expected: Vector3) {
+        let direction = ray.expect("Expected a ray").direction;
+        assert!((direction - expected).magnitude() < 1e-9,
+                "{:?} != {:?}",
+                direction,
+                expected);
+    }
+
+    let camera = Camera {
+        projection: Projection::Equirectangular,
+        ..Camera::default()
+    };
+
+    // Center is forward and the middle of the left half is to the left.
+    assert_direction(camera.create_prime(2.5, 1.5, 5, 3), -Vector3::unit_z());
+    assert_direction(camera.create_prime(0.5, 1.5, 2, 3), -Vector3::unit_x());
+
+    let camera = Camera {
+        projection: Projection::Fisheye { fov: 180.0 },
+        ..Camera::default()
+    };
+
+    // The rim of a 180° fisheye looks sideways
+    assert_direction(camera.create_prime(2.5, 2.5, 5, 5), -Vector3::unit_z());
+    assert_direction(camera.create_prime(3.5, 1.5, 4, 3), Vector3::unit_x());
+    assert!(camera.create_prime(0.5, 0.5, 5, 5).is_none());
+
+    let camera = Camera {
+        projection: Projection::Fisheye { fov: 360.0 },
+        ..Camera::default()
+    };
+
+    // ...while the rim of a 360° one looks straight backwards.
+    assert_direction(camera.create_prime(3.5, 1.5, 4, 3), Vector3::unit_z());
+}
+
+#[test]
+fn it_converges_stereo_eyes_at_the_convergence_distance() {
+    use stereo::StereoLayout;
+
+    let camera = Camera {
+        stereo: Some(Stereo {
+                         interocular_distance: 0.064,
+                         convergence_distance: 3.0,
+                         layout: StereoLayout::SideBySide,
+                     }),
+        ..Camera::default()
+    };
+
+    // The center pixel of both eyes should see the same point at the convergence distance.
+    for eye in [Eye::Left, Eye::Right] {
+        let eye_camera = camera.eye(eye);
+        assert!(eye_camera.stereo.is_none());
+
+        let ray = eye_camera.create_prime(1.5, 1.5, 3, 3).unwrap();
+        let distance = -3.0 / ray.direction.z;
+        let converged_point = ray.origin + ray.direction * distance;
+        assert!((converged_point - Point3::new(0.0, 0.0, -3.0)).magnitude() < 1e-9);
+    }
+}
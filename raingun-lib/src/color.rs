@@ -41,6 +41,19 @@ impl Color {
                    self.green.min(1.0).max(0.0),
                    self.blue.min(1.0).max(0.0))
     }
+
+    // Perceived brightness of the color.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
+
+    // Largest difference between the two colors in any single channel.
+    pub fn max_difference(&self, other: &Color) -> f32 {
+        (self.red - other.red)
+            .abs()
+            .max((self.green - other.green).abs())
+            .max((self.blue - other.blue).abs())
+    }
 }
 
 impl Default for Color {
@@ -190,6 +203,15 @@ fn it_displays_strings() {
     assert_eq!(Color::new(1.0, 0.5, 0.0).to_string(), "#ff7f00");
 }
 
+#[test]
+fn it_calculates_max_difference() {
+    let a = Color::new(0.25, 0.5, 1.0);
+    let b = Color::new(0.5, 0.0, 0.75);
+    assert_eq!(a.max_difference(&b), 0.5);
+    assert_eq!(b.max_difference(&a), 0.5);
+    assert_eq!(a.max_difference(&a), 0.0);
+}
+
 #[test]
 fn it_returns_same_color_as_input() {
     use std::str::FromStr;
@@ -0,0 +1,292 @@
+use serde;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use bodies::{Body, Span};
+use material::Material;
+use ray::Ray;
+use transform::Transform;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    // Takes all the other bodies away from the first one.
+    Difference,
+}
+
+impl CsgOperation {
+    // Whether a point is in the combined body, given whether it is in the first and second body.
+    fn contains(&self, in_first: bool, in_second: bool) -> bool {
+        match *self {
+            CsgOperation::Union => in_first || in_second,
+            CsgOperation::Intersection => in_first && in_second,
+            CsgOperation::Difference => in_first && !in_second,
+        }
+    }
+}
+
+// Constructive solid geometry: a body made by combining solid bodies. Hits use the material of the
+// body whose surface was hit.
+#[derive(Debug, Clone)]
+pub struct Csg {
+    operation: CsgOperation,
+    bodies: Vec<Body>,
+    transform: Option<Transform>,
+}
+
+// How a CSG body is described in the scene file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CsgDefinition {
+    operation: CsgOperation,
+    bodies: Vec<Body>,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation,
+               bodies: Vec<Body>,
+               transform: Option<Transform>)
+               -> Result<Csg, String> {
+        if bodies.is_empty() {
+            return Err(String::from("CSG bodies need at least one body to combine"));
+        }
+        check_solid(&bodies)?;
+
+        Ok(Csg {
+               operation,
+               bodies,
+               transform,
+           })
+    }
+
+    pub fn operation(&self) -> CsgOperation {
+        self.operation
+    }
+
+    pub fn bodies(&self) -> &[Body] {
+        &self.bodies
+    }
+
+    pub fn transform(&self) -> Option<&Transform> {
+        self.transform.as_ref()
+    }
+
+    // The material of the first body stands in for the whole body.
+    pub fn material(&self) -> Option<&Material> {
+        self.bodies[0].material()
+    }
+
+    // Links the instances among the bodies to their geometries, which have to be solid too.
+    pub fn resolve_instances(&mut self,
+                             geometries: &BTreeMap<String, Arc<Vec<Body>>>)
+                             -> Result<(), String> {
+        for body in &mut self.bodies {
+            body.resolve_instances(geometries)?;
+        }
+        check_solid(&self.bodies)
+    }
+
+    // Where the ray passes through the combined body.
+    pub fn spans(&self, ray: &Ray) -> Vec<Span<'_>> {
+        let first = self.bodies[0].spans(ray);
+
+        self.bodies[1..].iter().fold(first, |spans, body| {
+            // Nothing can be intersected with or taken away from nothing.
+            if spans.is_empty() && self.operation != CsgOperation::Union {
+                spans
+            } else {
+                combine(self.operation, spans, body.spans(ray))
+            }
+        })
+    }
+}
+
+fn check_solid(bodies: &[Body]) -> Result<(), String> {
+    match bodies.iter().position(|body| !body.is_solid()) {
+        Some(index) => {
+            Err(format!("Body {} can't be combined, only closed bodies like spheres and boxes can",
+                        index + 1))
+        }
+        None => Ok(()),
+    }
+}
+
+// Combines two lists of spans by following the ray through the boundaries of both, and keeping
+// the parts where the operation says that it is inside.
+pub fn combine<'a>(operation: CsgOperation,
+               first: Vec<Span<'a>>,
+               second: Vec<Span<'a>>)
+               -> Vec<Span<'a>> {
+    // Each boundary is kept with whether it belongs to the first spans, and whether the ray enters
+    // there.
+    let mut boundaries = Vec::with_capacity((first.len() + second.len()) * 2);
+    for span in first {
+        boundaries.push((span.entry, true, true));
+        boundaries.push((span.exit, true, false));
+    }
+    for span in second {
+        boundaries.push((span.entry, false, true));
+        boundaries.push((span.exit, false, false));
+    }
+    boundaries.sort_by(|a, b| {
+                           a.0
+                               .distance
+                               .partial_cmp(&b.0.distance)
+                               .unwrap_or(::std::cmp::Ordering::Equal)
+                       });
+
+    let mut spans = Vec::new();
+    let mut entry = None;
+    let (mut in_first, mut in_second) = (false, false);
+
+    for (mut boundary, is_first, entering) in boundaries {
+        let was_inside = operation.contains(in_first, in_second);
+        if is_first {
+            in_first = entering;
+        } else {
+            in_second = entering;
+        }
+        let inside = operation.contains(in_first, in_second);
+
+        if inside == was_inside {
+            continue;
+        }
+
+        // Where a body was taken away, its surface faces into it instead of out of it.
+        if operation == CsgOperation::Difference && !is_first {
+            boundary.normal = -boundary.normal;
+        }
+
+        if inside {
+            entry = Some(boundary);
+        } else if let Some(entry) = entry.take() {
+            spans.push(Span {
+                           entry,
+                           exit: boundary,
+                       });
+        }
+    }
+
+    spans
+}
+
+impl serde::Deserialize for Csg {
+    fn deserialize<D>(deserializer: D) -> Result<Csg, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let CsgDefinition { operation, bodies, transform } =
+            CsgDefinition::deserialize(deserializer)?;
+        Csg::new(operation, bodies, transform).map_err(Error::custom)
+    }
+}
+
+#[cfg(test)]
+fn test_sphere(z: f64) -> Body {
+    use bodies::Sphere;
+    use material::test_material;
+    use super::Point3;
+
+    Body::Sphere(Sphere {
+                     center: Point3::new(0.0, 0.0, z),
+                     radius: 1.0,
+                     material: test_material(),
+                     transform: None,
+                 })
+}
+
+#[test]
+fn it_carves_bodies_out_of_each_other() {
+    use bodies::{BoxTextureLayout, AABB};
+    use material::test_material;
+    use super::{Point3, Vector3};
+
+    // A sphere with its front half taken away by a box.
+    let box_in_front = Body::AABB(AABB {
+                                      bounds: [Point3::new(-2.0, -2.0, -5.0),
+                                               Point3::new(2.0, 2.0, -3.0)],
+                                      texture_layout: BoxTextureLayout::Repeated,
+                                      material: test_material(),
+                                      transform: None,
+                                  });
+    let csg = Csg::new(CsgOperation::Difference,
+                       vec![test_sphere(-5.0), box_in_front],
+                       None)
+            .unwrap();
+    let body = Body::Csg(csg);
+
+    let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), -Vector3::unit_z());
+    let intersection = body.intersection(&ray).unwrap();
+    assert!((intersection.distance - 5.0).abs() < 1e-9);
+    assert_eq!(intersection.normal, Vector3::unit_z());
+    assert!(::std::ptr::eq(intersection.body, &body));
+
+    // Starting inside of what is left, the ray hits the back of the sphere from the inside.
+    let ray = Ray::new(Point3::new(0.0, 0.0, -5.5), -Vector3::unit_z());
+    let intersection = body.intersection(&ray).unwrap();
+    assert!((intersection.distance - 0.5).abs() < 1e-9);
+    assert_eq!(intersection.normal, -Vector3::unit_z());
+}
+
+#[test]
+fn it_only_hits_surfaces_of_planes_at_a_finite_distance() {
+    use bodies::Plane;
+    use material::test_material;
+    use super::{Point3, Vector3};
+
+    // Everything below the ground, with a sphere taken out of it.
+    let ground = Body::Plane(Plane {
+                                 origin: Point3::new(0.0, 0.0, 0.0),
+                                 normal: -Vector3::unit_y(),
+                                 material: test_material(),
+                                 transform: None,
+                             });
+    let csg = Csg::new(CsgOperation::Difference, vec![ground, test_sphere(-5.0)], None).unwrap();
+    let body = Body::Csg(csg);
+
+    // Starting underground and going down, the ray never leaves the ground.
+    let ray = Ray::new(Point3::new(0.0, -1.0, 0.0), -Vector3::unit_y());
+    assert!(body.intersection(&ray).is_none());
+
+    // Along the ground, the ray only hits the hole that the sphere left.
+    let ray = Ray::new(Point3::new(0.0, -0.5, 0.0), -Vector3::unit_z());
+    let intersection = body.intersection(&ray).unwrap();
+    assert!((intersection.distance - (5.0 - 0.75f64.sqrt())).abs() < 1e-9);
+    let ray = Ray::new(Point3::new(3.0, -0.5, 0.0), -Vector3::unit_z());
+    assert!(body.intersection(&ray).is_none());
+}
+
+#[test]
+fn it_combines_spans() {
+    use material::test_material;
+    use super::{Point3, Vector3};
+
+    let spheres = || vec![test_sphere(-5.0), test_sphere(-6.0)];
+    let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), -Vector3::unit_z());
+    let distances = |operation| {
+        Csg::new(operation, spheres(), None)
+            .unwrap()
+            .spans(&ray)
+            .iter()
+            .map(|span| (span.entry.distance, span.exit.distance))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(distances(CsgOperation::Union), vec![(4.0, 7.0)]);
+    assert_eq!(distances(CsgOperation::Intersection), vec![(5.0, 6.0)]);
+    assert_eq!(distances(CsgOperation::Difference), vec![(4.0, 5.0)]);
+
+    let flat = Body::Triangle(::bodies::Triangle {
+                                  vertices: [Point3::new(0.0, 0.0, 0.0); 3],
+                                  normals: None,
+                                  texture_coords: None,
+                                  material: test_material(),
+                                  transform: None,
+                              });
+    assert!(Csg::new(CsgOperation::Union, vec![flat], None).is_err());
+}
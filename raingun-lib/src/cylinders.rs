@@ -0,0 +1,619 @@
+use serde;
+use std::f64::consts::PI;
+
+use bodies::{Intersectable, Solid};
+use cgmath::prelude::*;
+use material::{Material, TextureCoords};
+use ray::Ray;
+use roots::solve_quadratic;
+use transform::Transform;
+use super::{Point3, Vector3};
+
+// Points closer than this to the plane of a cap are on the cap.
+const CAP_EPSILON: f64 = 1e-8;
+
+fn default_caps() -> bool {
+    true
+}
+
+// A cylinder between the centers of its two ends.
+#[derive(Debug, Clone)]
+pub struct Cylinder {
+    pub start: Point3,
+    pub end: Point3,
+    pub radius: f64,
+    // Without caps on its ends the cylinder is an open tube.
+    pub caps: bool,
+    pub material: Material,
+    pub transform: Option<Transform>,
+}
+
+// How a cylinder is described in the scene file.
+#[derive(Deserialize)]
+struct CylinderDefinition {
+    start: Point3,
+    end: Point3,
+    radius: f64,
+    #[serde(default = "default_caps")]
+    caps: bool,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+// A cone between the centers of its two ends. It comes to a point at an end with a radius of zero,
+// and is truncated when neither radius is zero.
+#[derive(Debug, Clone)]
+pub struct Cone {
+    pub start: Point3,
+    pub end: Point3,
+    pub start_radius: f64,
+    pub end_radius: f64,
+    pub caps: bool,
+    pub material: Material,
+    pub transform: Option<Transform>,
+}
+
+// How a cone is described in the scene file. The end radius defaults to zero, for a pointy cone.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConeDefinition {
+    start: Point3,
+    end: Point3,
+    start_radius: f64,
+    #[serde(default)]
+    end_radius: f64,
+    #[serde(default = "default_caps")]
+    caps: bool,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+// A cylinder with half spheres on its ends. start and end are the centers of the half spheres.
+#[derive(Debug, Clone)]
+pub struct Capsule {
+    pub start: Point3,
+    pub end: Point3,
+    pub radius: f64,
+    pub material: Material,
+    pub transform: Option<Transform>,
+}
+
+// How a capsule is described in the scene file.
+#[derive(Deserialize)]
+struct CapsuleDefinition {
+    start: Point3,
+    end: Point3,
+    radius: f64,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+// How far around the axis direction the offset from the axis is, from 0 to 1.
+pub fn angle_around(direction: Vector3, offset: Vector3) -> f64 {
+    let mut x_axis = direction.cross(Vector3::unit_z());
+    if x_axis.magnitude2() < 1e-12 {
+        x_axis = direction.cross(Vector3::unit_y());
+    }
+    x_axis = x_axis.normalize();
+    let y_axis = direction.cross(x_axis);
+
+    (1.0 + offset.dot(y_axis).atan2(offset.dot(x_axis)) / PI) * 0.5
+}
+
+// Bodies around an axis need a start and an end in different places, or the axis has no
+// direction.
+fn check_axis(kind: &str, start: Point3, end: Point3) -> Result<(), String> {
+    if (end - start).magnitude2() > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("{} need a start and an end in different places", kind))
+    }
+}
+
+// A radius of zero leaves nothing to hit, and surfaces without any width have no normals.
+fn check_radius(kind: &str, radius: f64) -> Result<(), String> {
+    if radius > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("{} need a radius above zero", kind))
+    }
+}
+
+// Cones can come to a point at one of their ends, but not at both.
+fn check_cone_radii(start_radius: f64, end_radius: f64) -> Result<(), String> {
+    if start_radius < 0.0 || end_radius < 0.0 {
+        Err(String::from("Cones can't have a negative radius"))
+    } else if start_radius == 0.0 && end_radius == 0.0 {
+        Err(String::from("Cones need a radius above zero on at least one end"))
+    } else {
+        Ok(())
+    }
+}
+
+// The line from the start to the end of a body, which its surface goes around. The start and the
+// end can't be the same point; see check_axis.
+struct Axis {
+    start: Point3,
+    direction: Vector3,
+    length: f64,
+}
+
+impl Axis {
+    fn new(start: Point3, end: Point3) -> Axis {
+        let length = (end - start).magnitude();
+        Axis {
+            start,
+            direction: (end - start) / length,
+            length,
+        }
+    }
+
+    // Splits the position of a point into how far along the axis it is, and how far it is from
+    // the axis in which direction.
+    fn split(&self, point: &Point3) -> (f64, Vector3) {
+        let vector = point - self.start;
+        let along = vector.dot(self.direction);
+        (along, vector - self.direction * along)
+    }
+
+    fn angle(&self, offset: Vector3) -> f64 {
+        angle_around(self.direction, offset)
+    }
+
+    // Distances along the ray to where it hits the side of a cone around the axis, which is a
+    // cylinder when both radii are the same.
+    fn side_distances(&self, start_radius: f64, end_radius: f64, ray: &Ray) -> Vec<f64> {
+        let (origin_along, origin_offset) = self.split(&ray.origin);
+        let direction_along = ray.direction.dot(self.direction);
+        let direction_offset = ray.direction - self.direction * direction_along;
+
+        // The radius grows by slope for every unit along the axis. Points on the side are as far
+        // from the axis as the radius at their position, which makes a quadratic equation in the
+        // distance along the ray.
+        let slope = (end_radius - start_radius) / self.length;
+        let origin_radius = start_radius + slope * origin_along;
+
+        let a = direction_offset.magnitude2() - slope * slope * direction_along * direction_along;
+        let b = 2.0 *
+                (origin_offset.dot(direction_offset) - slope * direction_along * origin_radius);
+        let c = origin_offset.magnitude2() - origin_radius * origin_radius;
+
+        solve_quadratic(a, b, c)
+            .into_iter()
+            .filter(|distance| {
+                        let along = origin_along + direction_along * distance;
+                        along >= 0.0 && along <= self.length
+                    })
+            .collect()
+    }
+
+    // Distance along the ray to where it hits the disk around the axis, at the start or the end.
+    fn cap_distance(&self, at_end: bool, radius: f64, ray: &Ray) -> Option<f64> {
+        let denominator = ray.direction.dot(self.direction);
+        if denominator.abs() < 1e-12 || radius <= 0.0 {
+            return None;
+        }
+
+        let center = if at_end {
+            self.start + self.direction * self.length
+        } else {
+            self.start
+        };
+        let distance = (center - ray.origin).dot(self.direction) / denominator;
+        let hit_point = ray.origin + ray.direction * distance;
+
+        if (hit_point - center).magnitude2() <= radius * radius {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    // Distances along the ray to where it hits the sphere around the start or the end.
+    fn sphere_distances(&self, at_end: bool, radius: f64, ray: &Ray) -> Vec<f64> {
+        let center = if at_end {
+            self.start + self.direction * self.length
+        } else {
+            self.start
+        };
+        let offset = ray.origin - center;
+
+        solve_quadratic(1.0,
+                        2.0 * offset.dot(ray.direction),
+                        offset.magnitude2() - radius * radius)
+    }
+}
+
+// Distances along the ray to where it hits a cone, in order. Cylinders are cones with the same
+// radius at both ends.
+fn cone_distances(axis: &Axis,
+                  start_radius: f64,
+                  end_radius: f64,
+                  caps: bool,
+                  ray: &Ray)
+                  -> Vec<f64> {
+    let mut distances = axis.side_distances(start_radius, end_radius, ray);
+    if caps {
+        distances.extend(axis.cap_distance(false, start_radius, ray));
+        distances.extend(axis.cap_distance(true, end_radius, ray));
+    }
+    sort(&mut distances);
+    distances
+}
+
+// The cap that a point is on, if any, as which way it faces along the axis and its radius.
+fn cone_cap(axis: &Axis,
+            start_radius: f64,
+            end_radius: f64,
+            caps: bool,
+            along: f64)
+            -> Option<(f64, f64)> {
+    if !caps {
+        None
+    } else if along <= CAP_EPSILON && start_radius > 0.0 {
+        Some((-1.0, start_radius))
+    } else if along >= axis.length - CAP_EPSILON && end_radius > 0.0 {
+        Some((1.0, end_radius))
+    } else {
+        None
+    }
+}
+
+fn cone_normal(axis: &Axis,
+               start_radius: f64,
+               end_radius: f64,
+               caps: bool,
+               hit_point: &Point3)
+               -> Vector3 {
+    let (along, offset) = axis.split(hit_point);
+
+    match cone_cap(axis, start_radius, end_radius, caps, along) {
+        Some((facing, _)) => axis.direction * facing,
+        // The point of a cone has no direction away from the axis, so it faces along it.
+        None if offset.magnitude2() == 0.0 => axis.direction * (start_radius - end_radius).signum(),
+        None => {
+            // A cone's side leans towards the end where it is narrower.
+            let slope = (end_radius - start_radius) / axis.length;
+            (offset.normalize() - axis.direction * slope).normalize()
+        }
+    }
+}
+
+// Caps are mapped like disks, and the side is wrapped around the axis with the end at the top.
+fn cone_texture_coords(axis: &Axis,
+                       start_radius: f64,
+                       end_radius: f64,
+                       caps: bool,
+                       hit_point: &Point3)
+                       -> TextureCoords {
+    let (along, offset) = axis.split(hit_point);
+
+    match cone_cap(axis, start_radius, end_radius, caps, along) {
+        Some((_, radius)) => {
+            let angle = axis.angle(offset) * 2.0 * PI;
+            let distance = offset.magnitude() / radius;
+            TextureCoords {
+                x: (0.5 + angle.cos() * distance * 0.5) as f32,
+                y: (0.5 + angle.sin() * distance * 0.5) as f32,
+            }
+        }
+        None => {
+            TextureCoords {
+                x: axis.angle(offset) as f32,
+                y: (1.0 - along / axis.length) as f32,
+            }
+        }
+    }
+}
+
+fn sort(distances: &mut [f64]) {
+    distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+}
+
+fn first_in_front(distances: &[f64]) -> Option<f64> {
+    distances.iter().cloned().find(|&distance| distance >= 0.0)
+}
+
+// The shapes here are convex, so a ray passes through them at most once.
+fn outermost_span(distances: &[f64]) -> Vec<(f64, f64)> {
+    if distances.len() < 2 {
+        Vec::new()
+    } else {
+        vec![(distances[0], distances[distances.len() - 1])]
+    }
+}
+
+impl Cylinder {
+    fn axis(&self) -> Axis {
+        Axis::new(self.start, self.end)
+    }
+
+    fn distances(&self, ray: &Ray) -> Vec<f64> {
+        cone_distances(&self.axis(), self.radius, self.radius, self.caps, ray)
+    }
+}
+
+impl Intersectable for Cylinder {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        first_in_front(&self.distances(ray))
+    }
+
+    fn surface_normal(&self, hit_point: &Point3) -> Vector3 {
+        cone_normal(&self.axis(), self.radius, self.radius, self.caps, hit_point)
+    }
+
+    fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
+        cone_texture_coords(&self.axis(), self.radius, self.radius, self.caps, hit_point)
+    }
+}
+
+// Only cylinders with caps are closed.
+impl Solid for Cylinder {
+    fn span_distances(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        if self.caps {
+            outermost_span(&self.distances(ray))
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Cone {
+    fn axis(&self) -> Axis {
+        Axis::new(self.start, self.end)
+    }
+
+    fn distances(&self, ray: &Ray) -> Vec<f64> {
+        cone_distances(&self.axis(),
+                       self.start_radius,
+                       self.end_radius,
+                       self.caps,
+                       ray)
+    }
+}
+
+impl Intersectable for Cone {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        first_in_front(&self.distances(ray))
+    }
+
+    fn surface_normal(&self, hit_point: &Point3) -> Vector3 {
+        cone_normal(&self.axis(),
+                    self.start_radius,
+                    self.end_radius,
+                    self.caps,
+                    hit_point)
+    }
+
+    fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
+        cone_texture_coords(&self.axis(),
+                            self.start_radius,
+                            self.end_radius,
+                            self.caps,
+                            hit_point)
+    }
+}
+
+impl Solid for Cone {
+    fn span_distances(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        if self.caps {
+            outermost_span(&self.distances(ray))
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Capsule {
+    fn axis(&self) -> Axis {
+        Axis::new(self.start, self.end)
+    }
+
+    fn distances(&self, ray: &Ray) -> Vec<f64> {
+        let axis = self.axis();
+        let along = |distance: &f64| axis.split(&(ray.origin + ray.direction * *distance)).0;
+
+        let mut distances = axis.side_distances(self.radius, self.radius, ray);
+        distances.extend(axis.sphere_distances(false, self.radius, ray)
+                             .into_iter()
+                             .filter(|distance| along(distance) < 0.0));
+        distances.extend(axis.sphere_distances(true, self.radius, ray)
+                             .into_iter()
+                             .filter(|distance| along(distance) > axis.length));
+        sort(&mut distances);
+        distances
+    }
+}
+
+impl Intersectable for Capsule {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        first_in_front(&self.distances(ray))
+    }
+
+    fn surface_normal(&self, hit_point: &Point3) -> Vector3 {
+        // Points away from the closest point on the axis, which covers the ends as well.
+        let axis = self.axis();
+        let along = axis.split(hit_point).0.max(0.0).min(axis.length);
+        (hit_point - (axis.start + axis.direction * along)).normalize()
+    }
+
+    // Wrapped around the axis like a cylinder, from the tip of one end to the other.
+    fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
+        let axis = self.axis();
+        let (along, offset) = axis.split(hit_point);
+        let length = axis.length + 2.0 * self.radius;
+
+        TextureCoords {
+            x: axis.angle(offset) as f32,
+            y: (1.0 - (along + self.radius) / length).clamp(0.0, 1.0) as f32,
+        }
+    }
+}
+
+impl Solid for Capsule {
+    fn span_distances(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        outermost_span(&self.distances(ray))
+    }
+}
+
+impl serde::Deserialize for Cylinder {
+    fn deserialize<D>(deserializer: D) -> Result<Cylinder, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let CylinderDefinition { start, end, radius, caps, material, transform } =
+            CylinderDefinition::deserialize(deserializer)?;
+        check_axis("Cylinders", start, end).map_err(Error::custom)?;
+        check_radius("Cylinders", radius).map_err(Error::custom)?;
+
+        Ok(Cylinder {
+               start,
+               end,
+               radius,
+               caps,
+               material,
+               transform,
+           })
+    }
+}
+
+impl serde::Deserialize for Cone {
+    fn deserialize<D>(deserializer: D) -> Result<Cone, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let ConeDefinition { start, end, start_radius, end_radius, caps, material, transform } =
+            ConeDefinition::deserialize(deserializer)?;
+        check_axis("Cones", start, end).map_err(Error::custom)?;
+        check_cone_radii(start_radius, end_radius).map_err(Error::custom)?;
+
+        Ok(Cone {
+               start,
+               end,
+               start_radius,
+               end_radius,
+               caps,
+               material,
+               transform,
+           })
+    }
+}
+
+impl serde::Deserialize for Capsule {
+    fn deserialize<D>(deserializer: D) -> Result<Capsule, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let CapsuleDefinition { start, end, radius, material, transform } =
+            CapsuleDefinition::deserialize(deserializer)?;
+        check_axis("Capsules", start, end).map_err(Error::custom)?;
+        check_radius("Capsules", radius).map_err(Error::custom)?;
+
+        Ok(Capsule {
+               start,
+               end,
+               radius,
+               material,
+               transform,
+           })
+    }
+}
+
+#[test]
+fn it_intersects_cylinder_sides_and_caps() {
+    use material::test_material;
+
+    // Standing up along the Y axis, 4 units in front of the origin.
+    let mut cylinder = Cylinder {
+        start: Point3::new(0.0, -1.0, -4.0),
+        end: Point3::new(0.0, 1.0, -4.0),
+        radius: 1.0,
+        caps: true,
+        material: test_material(),
+        transform: None,
+    };
+
+    let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), -Vector3::unit_z());
+    assert_eq!(cylinder.intersect(&ray), Some(3.0));
+    assert_eq!(cylinder.span_distances(&ray), vec![(3.0, 5.0)]);
+    let normal = cylinder.surface_normal(&Point3::new(0.0, 0.0, -3.0));
+    assert!((normal - Vector3::unit_z()).magnitude() < 1e-9);
+
+    let ray = Ray::new(Point3::new(0.5, 5.0, -4.0), -Vector3::unit_y());
+    assert_eq!(cylinder.intersect(&ray), Some(4.0));
+    assert_eq!(cylinder.surface_normal(&Point3::new(0.5, 1.0, -4.0)),
+               Vector3::unit_y());
+
+    // Without caps the ray goes in through the top, and hits the inside of the side.
+    let ray = Ray::new(Point3::new(0.0, 1.5, -4.0),
+                       Vector3::new(1.0, -1.0, 0.0).normalize());
+    assert!((cylinder.intersect(&ray).unwrap() - 0.5 * 2.0f64.sqrt()).abs() < 1e-9);
+    cylinder.caps = false;
+    assert!((cylinder.intersect(&ray).unwrap() - 2.0f64.sqrt()).abs() < 1e-9);
+    assert!(cylinder.span_distances(&ray).is_empty());
+}
+
+#[test]
+fn it_intersects_cones_and_capsules() {
+    use material::test_material;
+
+    // A cone with its point at the top, as wide as it is high.
+    let cone = Cone {
+        start: Point3::new(0.0, 0.0, -4.0),
+        end: Point3::new(0.0, 1.0, -4.0),
+        start_radius: 1.0,
+        end_radius: 0.0,
+        caps: true,
+        material: test_material(),
+        transform: None,
+    };
+
+    let ray = Ray::new(Point3::new(0.0, 0.5, 0.0), -Vector3::unit_z());
+    assert_eq!(cone.span_distances(&ray), vec![(3.5, 4.5)]);
+    let normal = cone.surface_normal(&Point3::new(0.0, 0.5, -3.5));
+    assert!((normal - Vector3::new(0.0, 1.0, 1.0).normalize()).magnitude() < 1e-9);
+
+    // Straight down onto the point.
+    let ray = Ray::new(Point3::new(0.0, 5.0, -4.0), -Vector3::unit_y());
+    assert_eq!(cone.intersect(&ray), Some(4.0));
+    assert_eq!(cone.surface_normal(&Point3::new(0.0, 1.0, -4.0)), Vector3::unit_y());
+
+    let capsule = Capsule {
+        start: Point3::new(-1.0, 0.0, -4.0),
+        end: Point3::new(1.0, 0.0, -4.0),
+        radius: 0.5,
+        material: test_material(),
+        transform: None,
+    };
+
+    let ray = Ray::new(Point3::new(-5.0, 0.0, -4.0), Vector3::unit_x());
+    assert_eq!(capsule.span_distances(&ray), vec![(3.5, 6.5)]);
+    assert_eq!(capsule.surface_normal(&Point3::new(1.5, 0.0, -4.0)),
+               Vector3::unit_x());
+    assert_eq!(capsule.texture_coords(&Point3::new(-1.5, 0.0, -4.0)).y, 1.0);
+}
+
+#[test]
+fn it_rejects_axes_without_a_direction() {
+    let point = Point3::new(1.0, 2.0, 3.0);
+    assert!(check_axis("Cylinders", point, point).is_err());
+    assert!(check_axis("Cylinders", point, Point3::new(1.0, 2.0, 4.0)).is_ok());
+}
+
+#[test]
+fn it_rejects_radii_without_a_width() {
+    assert!(check_radius("Capsules", 0.0).is_err());
+    assert!(check_radius("Capsules", -1.0).is_err());
+    assert!(check_radius("Capsules", 0.5).is_ok());
+
+    assert!(check_cone_radii(0.0, 0.0).is_err());
+    assert!(check_cone_radii(1.0, -0.5).is_err());
+    assert!(check_cone_radii(1.0, 0.0).is_ok());
+    assert!(check_cone_radii(0.0, 2.0).is_ok());
+}
@@ -0,0 +1,297 @@
+use image::{ImageBuffer, Rgba, Pixel};
+
+use color::Color;
+
+use std::f64::consts::PI;
+
+// Reconstruction filter used to turn samples into pixels. Every sample contributes to all pixels
+// whose centers are within radius pixels of it, weighted by the filter's shape.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields, default, rename_all = "camelCase")]
+pub struct Filter {
+    pub shape: FilterShape,
+    pub radius: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum FilterShape {
+    // Every sample within the radius is weighted equally.
+    Box,
+    // Weights fall off linearly towards the radius.
+    Tent,
+    // Gaussian bell curve, shifted down to reach zero at the radius. Higher alpha falls off
+    // faster.
+    Gaussian { alpha: f64 },
+    // Mitchell-Netravali cubic, scaled to fit the radius. B = C = 1/3 is the classic choice.
+    Mitchell { b: f64, c: f64 },
+    // Sinc with tau lobes on each side, windowed by a wider sinc that reaches zero at the radius.
+    Lanczos { tau: f64 },
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        // Only sees samples inside of the pixel itself, which is the same as averaging them.
+        Filter {
+            shape: FilterShape::Box,
+            radius: 0.5,
+        }
+    }
+}
+
+impl Filter {
+    // Weight of a sample that is at the given offset from the pixel's center.
+    pub fn weight(&self, x: f64, y: f64) -> f64 {
+        self.weight_1d(x) * self.weight_1d(y)
+    }
+
+    fn weight_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+
+        match self.shape {
+            FilterShape::Box => 1.0,
+            FilterShape::Tent => self.radius - x,
+            FilterShape::Gaussian { alpha } => {
+                ((-alpha * x * x).exp() - (-alpha * self.radius * self.radius).exp()).max(0.0)
+            }
+            FilterShape::Mitchell { b, c } => {
+                // The cubic is defined from -2 to 2
+                let x = 2.0 * x / self.radius;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x +
+                     (6.0 - 2.0 * b)) / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x +
+                     (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+                }
+            }
+            FilterShape::Lanczos { tau } => {
+                let x = x / self.radius;
+                sinc(tau * x) * sinc(x)
+            }
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct FilmPixel {
+    weighted_sum: Color,
+    weight_sum: f32,
+    // Statistics of the samples inside of the pixel itself, used to estimate its noise.
+    sample_count: u32,
+    luminance_sum: f64,
+    luminance_squared_sum: f64,
+}
+
+// Accumulates samples into pixels using a reconstruction filter.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    filter: Filter,
+    pixels: Vec<FilmPixel>,
+    samples: u64,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Film {
+        let empty_pixel = FilmPixel {
+            weighted_sum: Color::black(),
+            weight_sum: 0.0,
+            sample_count: 0,
+            luminance_sum: 0.0,
+            luminance_squared_sum: 0.0,
+        };
+
+        Film {
+            width,
+            height,
+            filter,
+            pixels: vec![empty_pixel; (width * height) as usize],
+            samples: 0,
+        }
+    }
+
+    // How many rows above and below a sample's own row that it can contribute to.
+    pub fn reach(&self) -> u32 {
+        self.filter.radius.ceil() as u32
+    }
+
+    // Adds a sample at the given position on the image, where pixel (x, y) covers the area from x
+    // to x + 1.0 and y to y + 1.0.
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Color) {
+        self.samples += 1;
+
+        if x >= 0.0 && y >= 0.0 && x < self.width as f64 && y < self.height as f64 {
+            let luminance = color.luminance() as f64;
+            let pixel = &mut self.pixels[(y as u32 * self.width + x as u32) as usize];
+            pixel.sample_count += 1;
+            pixel.luminance_sum += luminance;
+            pixel.luminance_squared_sum += luminance * luminance;
+        }
+
+        let radius = self.filter.radius;
+
+        // Pixels whose centers are within the filter's radius
+        let min_x = (x - 0.5 - radius).ceil().max(0.0) as u32;
+        let max_x = (x - 0.5 + radius).floor().min(self.width as f64 - 1.0);
+        let min_y = (y - 0.5 - radius).ceil().max(0.0) as u32;
+        let max_y = (y - 0.5 + radius).floor().min(self.height as f64 - 1.0);
+
+        if max_x < 0.0 || max_y < 0.0 {
+            return;
+        }
+
+        for pixel_y in min_y..(max_y as u32 + 1) {
+            for pixel_x in min_x..(max_x as u32 + 1) {
+                let weight = self.filter
+                    .weight(x - (pixel_x as f64 + 0.5), y - (pixel_y as f64 + 0.5)) as
+                             f32;
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let pixel = &mut self.pixels[(pixel_y * self.width + pixel_x) as usize];
+                pixel.weighted_sum = pixel.weighted_sum + color * weight;
+                pixel.weight_sum += weight;
+            }
+        }
+    }
+
+    pub fn color(&self, x: u32, y: u32) -> Color {
+        let pixel = &self.pixels[(y * self.width + x) as usize];
+
+        // Filters with negative lobes can make the weights cancel out, and they can overshoot.
+        if pixel.weight_sum.abs() < 1e-6 {
+            Color::black()
+        } else {
+            (pixel.weighted_sum * (1.0 / pixel.weight_sum)).clamp()
+        }
+    }
+
+    // Total number of samples added to the film.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    // Number of samples that were inside of the pixel.
+    pub fn sample_count(&self, x: u32, y: u32) -> u32 {
+        self.pixels[(y * self.width + x) as usize].sample_count
+    }
+
+    // Estimated noise of the pixel: the standard error of the mean luminance of the samples inside
+    // of it. None until the pixel has at least two samples.
+    pub fn noise(&self, x: u32, y: u32) -> Option<f64> {
+        let pixel = &self.pixels[(y * self.width + x) as usize];
+        if pixel.sample_count < 2 {
+            return None;
+        }
+
+        let count = pixel.sample_count as f64;
+        let mean = pixel.luminance_sum / count;
+        let variance = ((pixel.luminance_squared_sum - mean * pixel.luminance_sum) / (count - 1.0))
+            .max(0.0);
+
+        Some((variance / count).sqrt())
+    }
+
+    pub fn to_image(&self) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let mut raw_image = Vec::with_capacity((self.width * self.height * 4) as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                raw_image.extend_from_slice(self.color(x, y).rgba().channels());
+            }
+        }
+
+        ImageBuffer::from_raw(self.width, self.height, raw_image).unwrap()
+    }
+}
+
+#[test]
+fn it_averages_samples_with_the_default_box_filter() {
+    let mut film = Film::new(2, 1, Filter::default());
+    film.add_sample(0.25, 0.5, Color::new(1.0, 0.0, 0.0));
+    film.add_sample(0.75, 0.5, Color::new(0.0, 0.0, 1.0));
+    film.add_sample(1.5, 0.5, Color::new(0.0, 1.0, 0.0));
+
+    assert_eq!(film.color(0, 0), Color::new(0.5, 0.0, 0.5));
+    assert_eq!(film.color(1, 0), Color::new(0.0, 1.0, 0.0));
+}
+
+#[test]
+fn it_splats_samples_into_neighbouring_pixels() {
+    let filter = Filter {
+        shape: FilterShape::Tent,
+        radius: 1.5,
+    };
+    let mut film = Film::new(3, 1, filter);
+    film.add_sample(0.5, 0.5, Color::new(1.0, 1.0, 1.0));
+    film.add_sample(2.5, 0.5, Color::new(0.0, 0.0, 0.0));
+
+    // Middle pixel is equally far from both samples
+    assert_eq!(film.color(1, 0), Color::new(0.5, 0.5, 0.5));
+    assert_eq!(film.color(0, 0), Color::new(1.0, 1.0, 1.0));
+}
+
+#[test]
+fn it_has_filters_that_fall_off_towards_the_radius() {
+    let shapes = vec![FilterShape::Box,
+                      FilterShape::Tent,
+                      FilterShape::Gaussian { alpha: 2.0 },
+                      FilterShape::Mitchell {
+                          b: 1.0 / 3.0,
+                          c: 1.0 / 3.0,
+                      },
+                      FilterShape::Lanczos { tau: 3.0 }];
+
+    for shape in shapes {
+        let filter = Filter { shape, radius: 2.0 };
+
+        assert!(filter.weight(0.0, 0.0) > 0.0, "{:?}", shape);
+        assert!(filter.weight(0.0, 0.0) >= filter.weight(0.5, 0.0), "{:?}", shape);
+        assert!(filter.weight(2.0, 0.0).abs() < 1e-9 || shape == FilterShape::Box,
+                "{:?}",
+                shape);
+        assert_eq!(filter.weight(2.1, 0.0), 0.0, "{:?}", shape);
+    }
+}
+
+#[test]
+fn it_windows_lanczos_filters_to_the_radius() {
+    let filter = Filter {
+        shape: FilterShape::Lanczos { tau: 2.0 },
+        radius: 3.0,
+    };
+
+    assert_eq!(filter.weight(0.0, 0.0), 1.0);
+    // The zero crossing between the two lobes, and the end of the window.
+    assert!(filter.weight(1.5, 0.0).abs() < 1e-9);
+    assert!(filter.weight(3.0, 0.0).abs() < 1e-9);
+    assert!(filter.weight(2.25, 0.0) < 0.0);
+    assert!(filter.weight(2.9, 0.0) < 0.0);
+}
+
+#[test]
+fn it_estimates_noise_from_the_samples_inside_of_pixels() {
+    let mut film = Film::new(2, 1, Filter::default());
+    film.add_sample(0.25, 0.5, Color::new(1.0, 1.0, 1.0));
+    assert_eq!(film.noise(0, 0), None);
+
+    film.add_sample(0.75, 0.5, Color::new(1.0, 1.0, 1.0));
+    film.add_sample(1.25, 0.5, Color::new(0.0, 0.0, 0.0));
+    film.add_sample(1.75, 0.5, Color::new(1.0, 1.0, 1.0));
+
+    assert_eq!(film.samples(), 4);
+    assert_eq!(film.sample_count(0, 0), 2);
+    assert!(film.noise(0, 0).unwrap() < 1e-6);
+    assert!((film.noise(1, 0).unwrap() - 0.5).abs() < 1e-6);
+}
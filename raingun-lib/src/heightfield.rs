@@ -0,0 +1,321 @@
+use serde;
+use std::fmt;
+
+use bodies::{intersect_triangle, triangle_surface_normal, Intersectable};
+use boxes::slab_distances;
+use cgmath::prelude::*;
+use image::DynamicImage;
+use material::{load_texture, Material, TextureCoords};
+use ray::Ray;
+use transform::Transform;
+use super::{Point3, Vector3};
+
+// Terrain made from a grid of heights, with two triangles between every four neighboring heights.
+// The grid lies along the X and Z axes from the origin, and the heights go up along the Y axis.
+#[derive(Clone)]
+pub struct Heightfield {
+    origin: Point3,
+    // Size of the terrain along the X and Z axes.
+    extent: [f64; 2],
+    columns: usize,
+    rows: usize,
+    // Height above the origin of each point in the grid, row by row, starting at the smallest Z.
+    heights: Vec<f64>,
+    normals: Vec<Vector3>,
+    lowest: f64,
+    highest: f64,
+    material: Material,
+    transform: Option<Transform>,
+}
+
+// How a heightfield is described in the scene file. Each pixel of the image is a point in the
+// grid, where black is at the origin's height and white is height_scale above it. The top row of
+// the image is at the smallest Z.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeightfieldDefinition {
+    #[serde(deserialize_with = "load_texture")]
+    image: DynamicImage,
+    origin: Point3,
+    extent: [f64; 2],
+    height_scale: f64,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+impl Heightfield {
+    pub fn new(origin: Point3,
+               extent: [f64; 2],
+               columns: usize,
+               heights: Vec<f64>,
+               material: Material,
+               transform: Option<Transform>)
+               -> Result<Heightfield, String> {
+        let rows = heights.len() / columns.max(1);
+        if columns < 2 || rows < 2 || rows * columns != heights.len() {
+            return Err(String::from("Heightfields need at least two rows of at least two heights"));
+        }
+        if extent[0] <= 0.0 || extent[1] <= 0.0 {
+            return Err(String::from("Heightfields need a positive extent"));
+        }
+
+        let mut heightfield = Heightfield {
+            origin,
+            extent,
+            columns,
+            rows,
+            lowest: heights.iter().cloned().fold(f64::INFINITY, f64::min),
+            highest: heights.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            heights,
+            normals: Vec::new(),
+            material,
+            transform,
+        };
+        heightfield.normals = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| heightfield.grid_normal(column, row))
+            .collect();
+
+        Ok(heightfield)
+    }
+
+    // Uses the brightness of each pixel as its height. The image library only decodes 8 bits per
+    // channel, so 16-bit heightmaps are read with 256 levels of height.
+    pub fn from_image(image: &DynamicImage,
+                      origin: Point3,
+                      extent: [f64; 2],
+                      height_scale: f64,
+                      material: Material,
+                      transform: Option<Transform>)
+                      -> Result<Heightfield, String> {
+        let luma = image.to_luma();
+        let heights = luma.pixels()
+            .map(|pixel| pixel.data[0] as f64 / 255.0 * height_scale)
+            .collect();
+
+        Heightfield::new(origin,
+                         extent,
+                         luma.width() as usize,
+                         heights,
+                         material,
+                         transform)
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    pub fn transform(&self) -> Option<&Transform> {
+        self.transform.as_ref()
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (self.extent[0] / (self.columns - 1) as f64, self.extent[1] / (self.rows - 1) as f64)
+    }
+
+    fn height(&self, column: usize, row: usize) -> f64 {
+        self.heights[row * self.columns + column]
+    }
+
+    fn point(&self, column: usize, row: usize) -> Point3 {
+        let (width, depth) = self.cell_size();
+        let offset = Vector3::new(column as f64 * width,
+                                  self.height(column, row),
+                                  row as f64 * depth);
+        self.origin + offset
+    }
+
+    // The normal at a point in the grid, from the slope between its neighbors.
+    fn grid_normal(&self, column: usize, row: usize) -> Vector3 {
+        let (width, depth) = self.cell_size();
+        let (left, right) = (column.saturating_sub(1), (column + 1).min(self.columns - 1));
+        let (back, front) = (row.saturating_sub(1), (row + 1).min(self.rows - 1));
+
+        let x_slope = (self.height(right, row) - self.height(left, row)) /
+                      ((right - left) as f64 * width);
+        let z_slope = (self.height(column, front) - self.height(column, back)) /
+                      ((front - back) as f64 * depth);
+        Vector3::new(-x_slope, 1.0, -z_slope).normalize()
+    }
+
+    // The two triangles of a cell, counter-clockwise when seen from above. They are split along
+    // the diagonal from the cell's corner at the larger X and smaller Z.
+    fn triangles(&self, column: usize, row: usize) -> [[(usize, usize); 3]; 2] {
+        [[(column, row), (column, row + 1), (column + 1, row)],
+         [(column + 1, row), (column, row + 1), (column + 1, row + 1)]]
+    }
+
+    fn vertices(&self, triangle: &[(usize, usize); 3]) -> [Point3; 3] {
+        [self.point(triangle[0].0, triangle[0].1),
+         self.point(triangle[1].0, triangle[1].1),
+         self.point(triangle[2].0, triangle[2].1)]
+    }
+
+    // The cell and triangle that a point above or below the grid is in.
+    fn triangle_at(&self, point: &Point3) -> [(usize, usize); 3] {
+        let (width, depth) = self.cell_size();
+        let x = (point.x - self.origin.x) / width;
+        let z = (point.z - self.origin.z) / depth;
+        let column = clamp_cell(x, self.columns);
+        let row = clamp_cell(z, self.rows);
+
+        let triangles = self.triangles(column, row);
+        if (x - column as f64) + (z - row as f64) <= 1.0 {
+            triangles[0]
+        } else {
+            triangles[1]
+        }
+    }
+}
+
+// The cell that a position in the grid is in, counting points on the far edge as part of the last
+// cell.
+fn clamp_cell(position: f64, points: usize) -> usize {
+    (position.max(0.0).floor() as usize).min(points - 2)
+}
+
+// How the ray moves through the cells along one axis of the grid: which way it steps, the distance
+// along the ray to the first boundary between cells, and the distance between boundaries.
+fn grid_steps(offset: f64, direction: f64, cell_size: f64, cell: usize) -> (isize, f64, f64) {
+    if direction > 0.0 {
+        (1, ((cell + 1) as f64 * cell_size - offset) / direction, cell_size / direction)
+    } else if direction < 0.0 {
+        (-1, (cell as f64 * cell_size - offset) / direction, -cell_size / direction)
+    } else {
+        (0, f64::INFINITY, f64::INFINITY)
+    }
+}
+
+impl Intersectable for Heightfield {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        // Only the part of the ray that is inside the bounding box needs to be checked.
+        let half_extents = Vector3::new(self.extent[0],
+                                        self.highest - self.lowest,
+                                        self.extent[1]) * 0.5;
+        let center = self.origin + Vector3::new(0.0, self.lowest, 0.0) + half_extents;
+        let centered_ray = Ray::new(Point3::from_vec(ray.origin - center), ray.direction);
+        let (entry, exit) = match slab_distances(&half_extents, &centered_ray) {
+            Some((entry, exit)) if exit >= 0.0 => (entry.max(0.0), exit),
+            _ => return None,
+        };
+
+        // Walks through the cells that the ray passes over, in order, so the first triangle that
+        // is hit is the closest one.
+        let (width, depth) = self.cell_size();
+        let start = ray.origin + ray.direction * entry;
+        let mut column = clamp_cell((start.x - self.origin.x) / width, self.columns);
+        let mut row = clamp_cell((start.z - self.origin.z) / depth, self.rows);
+
+        let (column_step, mut next_column, column_delta) =
+            grid_steps(ray.origin.x - self.origin.x, ray.direction.x, width, column);
+        let (row_step, mut next_row, row_delta) =
+            grid_steps(ray.origin.z - self.origin.z, ray.direction.z, depth, row);
+
+        loop {
+            let hit = self.triangles(column, row)
+                .iter()
+                .filter_map(|triangle| intersect_triangle(&self.vertices(triangle), ray))
+                .fold(None, |closest: Option<f64>, distance| match closest {
+                    Some(closest) if closest < distance => Some(closest),
+                    _ => Some(distance),
+                });
+            if hit.is_some() {
+                return hit;
+            }
+
+            if next_column < next_row {
+                if next_column > exit {
+                    return None;
+                }
+                column = (column as isize + column_step) as usize;
+                next_column += column_delta;
+            } else {
+                if next_row > exit {
+                    return None;
+                }
+                row = (row as isize + row_step) as usize;
+                next_row += row_delta;
+            }
+
+            // Stepping below zero wraps around, so this also catches leaving at the near edges.
+            if column >= self.columns - 1 || row >= self.rows - 1 {
+                return None;
+            }
+        }
+    }
+
+    fn surface_normal(&self, hit_point: &Point3) -> Vector3 {
+        // Blended from the normals at the corners of the triangle, so the terrain looks smooth.
+        let triangle = self.triangle_at(hit_point);
+        let normals = [self.normals[triangle[0].1 * self.columns + triangle[0].0],
+                       self.normals[triangle[1].1 * self.columns + triangle[1].0],
+                       self.normals[triangle[2].1 * self.columns + triangle[2].0]];
+        triangle_surface_normal(&self.vertices(&triangle), &Some(normals), hit_point)
+    }
+
+    // A single texture covers the whole terrain as seen from above, the same way around as the
+    // image that the heights came from.
+    fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
+        TextureCoords {
+            x: ((hit_point.x - self.origin.x) / self.extent[0]) as f32,
+            y: ((hit_point.z - self.origin.z) / self.extent[1]) as f32,
+        }
+    }
+}
+
+impl serde::Deserialize for Heightfield {
+    fn deserialize<D>(deserializer: D) -> Result<Heightfield, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let HeightfieldDefinition { image, origin, extent, height_scale, material, transform } =
+            HeightfieldDefinition::deserialize(deserializer)?;
+        Heightfield::from_image(&image, origin, extent, height_scale, material, transform)
+            .map_err(Error::custom)
+    }
+}
+
+impl fmt::Debug for Heightfield {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Heightfield with {}×{} heights", self.columns, self.rows)
+    }
+}
+
+#[test]
+fn it_intersects_heightfields() {
+    use material::test_material;
+
+    // A flat square with a single peak in the middle of the far edge.
+    let heights = vec![0.0, 0.0, 0.0, //
+                       0.0, 0.0, 0.0, //
+                       0.0, 2.0, 0.0];
+    let heightfield = Heightfield::new(Point3::new(-1.0, 0.0, -1.0),
+                                       [2.0, 2.0],
+                                       3,
+                                       heights,
+                                       test_material(),
+                                       None)
+            .unwrap();
+
+    // Straight down onto the flat part.
+    let ray = Ray::new(Point3::new(-0.5, 5.0, -0.5), -Vector3::unit_y());
+    assert!((heightfield.intersect(&ray).unwrap() - 5.0).abs() < 1e-9);
+    let normal = heightfield.surface_normal(&Point3::new(-0.5, 0.0, -0.5));
+    assert!((normal - Vector3::unit_y()).magnitude() < 1e-9);
+    let texture_coords = heightfield.texture_coords(&Point3::new(-0.5, 0.0, -0.5));
+    assert_eq!((texture_coords.x, texture_coords.y), (0.25, 0.25));
+
+    // Along the ground from the near side, passing over cells until it runs into the peak.
+    let ray = Ray::new(Point3::new(0.0, 1.0, -3.0), Vector3::unit_z());
+    assert!((heightfield.intersect(&ray).unwrap() - 3.5).abs() < 1e-9);
+    let normal = heightfield.surface_normal(&Point3::new(0.0, 1.0, 0.5));
+    assert!(normal.z < 0.0 && normal.y > 0.0);
+
+    // Above the peak, and next to the terrain.
+    let ray = Ray::new(Point3::new(0.0, 2.5, -3.0), Vector3::unit_z());
+    assert_eq!(heightfield.intersect(&ray), None);
+    let ray = Ray::new(Point3::new(1.5, 5.0, 0.0), -Vector3::unit_y());
+    assert_eq!(heightfield.intersect(&ray), None);
+}
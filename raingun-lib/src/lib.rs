@@ -1,6 +1,8 @@
+extern crate byteorder;
 extern crate cgmath;
 extern crate image;
 extern crate parking_lot;
+extern crate rand;
 extern crate rayon;
 extern crate serde;
 
@@ -11,19 +13,45 @@ extern crate serde_derive;
 const SHADOW_BIAS: f64 = 1e-13;
 
 mod bodies;
+mod boxes;
+mod camera;
 mod color;
+mod csg;
+mod cylinders;
+mod film;
+mod heightfield;
 mod lights;
+mod marching;
+mod mesh;
 mod ray;
 mod rendering;
+mod roots;
+mod sampling;
 mod scene;
+mod sdf;
+mod stereo;
+mod torus;
+mod transform;
 pub mod material;
 
-pub use bodies::{Body, Sphere, Plane};
+pub use bodies::{Body, BoxTextureLayout, Disk, Instance, Sphere, Plane, Rectangle, Triangle, AABB};
+pub use boxes::{OrientedBox, RoundedBox};
+pub use camera::{Camera, FovAxis, Projection};
 pub use color::Color;
+pub use csg::{Csg, CsgOperation};
+pub use cylinders::{Capsule, Cone, Cylinder};
+pub use film::{Filter, FilterShape};
+pub use heightfield::Heightfield;
 pub use lights::{Light, DirectionalLight, SphericalLight};
+pub use mesh::Mesh;
 pub use ray::Ray;
 pub use scene::Scene;
-pub use rendering::RenderedPixel;
+pub use sdf::{Sdf, SdfShape};
+pub use stereo::{Eye, Stereo, StereoLayout};
+pub use torus::Torus;
+pub use transform::Transform;
+pub use rendering::{RenderedPass, RenderedPixel};
+pub use sampling::{AdaptiveSampling, SamplingPattern};
 pub use cgmath::prelude::*;
 
 pub type Point3 = cgmath::Point3<f64>;
@@ -0,0 +1,46 @@
+use ray::Ray;
+use super::Point3;
+
+// Finds where the ray first gets within epsilon of a surface described by a signed distance
+// function, which gives the distance from a point to the closest surface (negative inside). The
+// ray can safely move that far every step. Starts at the ray's origin and gives up after
+// max_distance or max_steps, which happens when the ray only grazes the surface.
+pub fn sphere_trace<F>(distance: F,
+                       ray: &Ray,
+                       max_distance: f64,
+                       max_steps: u32,
+                       epsilon: f64)
+                       -> Option<f64>
+    where F: Fn(&Point3) -> f64
+{
+    let mut traveled = 0.0;
+
+    for _ in 0..max_steps {
+        let step = distance(&(ray.origin + ray.direction * traveled));
+        if step < epsilon {
+            return Some(traveled);
+        }
+
+        traveled += step;
+        if traveled > max_distance {
+            return None;
+        }
+    }
+
+    None
+}
+
+#[test]
+fn it_traces_spheres() {
+    use cgmath::prelude::*;
+    use super::Vector3;
+
+    let sphere = |point: &Point3| point.to_vec().magnitude() - 1.0;
+
+    let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), -Vector3::unit_z());
+    let distance = sphere_trace(sphere, &ray, 10.0, 64, 1e-9).unwrap();
+    assert!((distance - 4.0).abs() < 1e-8);
+
+    let ray = Ray::new(Point3::new(0.0, 1.5, 5.0), -Vector3::unit_z());
+    assert_eq!(sphere_trace(sphere, &ray, 10.0, 64, 1e-9), None);
+}
@@ -11,7 +11,7 @@ pub struct Material {
     pub surface: Surface,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Deserialize)]
 pub struct TextureCoords {
     pub x: f32,
     pub y: f32,
@@ -31,7 +31,7 @@ pub struct Texture {
     pub y_offset: f32,
 }
 
-fn load_texture<D>(deserializer: D) -> Result<DynamicImage, D::Error>
+pub fn load_texture<D>(deserializer: D) -> Result<DynamicImage, D::Error>
     where D: serde::Deserializer
 {
     use image;
@@ -59,6 +59,16 @@ impl Material {
     }
 }
 
+// A plain material for bodies in tests, where only their shape matters.
+#[cfg(test)]
+pub fn test_material() -> Material {
+    Material {
+        coloration: Coloration::Color(Color::black()),
+        albedo: 0.0,
+        surface: Surface::Diffuse,
+    }
+}
+
 impl Texture {
     pub fn color(&self, texture_coords: &TextureCoords) -> Color {
         let x = Texture::wrap(texture_coords.x + self.x_offset, self.image.width());
@@ -0,0 +1,257 @@
+use ray::Ray;
+use super::super::Point3;
+
+// Primitives per leaf node. Testing a handful of them directly is cheaper than another level of
+// boxes.
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl BoundingBox {
+    pub fn empty() -> BoundingBox {
+        BoundingBox {
+            min: Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points(points: &[Point3]) -> BoundingBox {
+        points
+            .iter()
+            .fold(BoundingBox::empty(), |bounds, point| bounds.grow(point))
+    }
+
+    pub fn grow(&self, point: &Point3) -> BoundingBox {
+        BoundingBox {
+            min: Point3::new(self.min.x.min(point.x),
+                             self.min.y.min(point.y),
+                             self.min.z.min(point.z)),
+            max: Point3::new(self.max.x.max(point.x),
+                             self.max.y.max(point.y),
+                             self.max.z.max(point.z)),
+        }
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        self.grow(&other.min).grow(&other.max)
+    }
+
+    pub fn center(&self) -> Point3 {
+        Point3::new((self.min.x + self.max.x) / 2.0,
+                    (self.min.y + self.max.y) / 2.0,
+                    (self.min.z + self.max.z) / 2.0)
+    }
+
+    // Index of the axis (0 for X, 1 for Y, 2 for Z) that the box is the longest along.
+    pub fn longest_axis(&self) -> usize {
+        let size = self.max - self.min;
+        if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    // Distance along the ray to where it enters the box, or zero when it starts inside of it.
+    pub fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let bounds = [self.min, self.max];
+
+        let mut tmin = (bounds[ray.signs[0]].x - ray.origin.x) * ray.inverted_direction.x;
+        let mut tmax = (bounds[1 - ray.signs[0]].x - ray.origin.x) * ray.inverted_direction.x;
+
+        let tymin = (bounds[ray.signs[1]].y - ray.origin.y) * ray.inverted_direction.y;
+        let tymax = (bounds[1 - ray.signs[1]].y - ray.origin.y) * ray.inverted_direction.y;
+
+        if tmin > tymax || tymin > tmax {
+            return None;
+        }
+        tmin = tmin.max(tymin);
+        tmax = tmax.min(tymax);
+
+        let tzmin = (bounds[ray.signs[2]].z - ray.origin.z) * ray.inverted_direction.z;
+        let tzmax = (bounds[1 - ray.signs[2]].z - ray.origin.z) * ray.inverted_direction.z;
+
+        if tmin > tzmax || tzmin > tmax {
+            return None;
+        }
+        tmin = tmin.max(tzmin);
+        tmax = tmax.min(tzmax);
+
+        if tmax < 0.0 { None } else { Some(tmin.max(0.0)) }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    // Primitives from first to first + count in the BVH's primitive list.
+    Leaf {
+        bounds: BoundingBox,
+        first: usize,
+        count: usize,
+    },
+    // The first child always comes right after its parent.
+    Interior {
+        bounds: BoundingBox,
+        second_child: usize,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> &BoundingBox {
+        match *self {
+            Node::Leaf { ref bounds, .. } => bounds,
+            Node::Interior { ref bounds, .. } => bounds,
+        }
+    }
+}
+
+// Bounding volume hierarchy: a tree of boxes around primitives, so that rays only need to be
+// tested against the primitives in the boxes that they pass through.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    primitives: Vec<usize>,
+}
+
+impl Bvh {
+    // Builds the hierarchy for primitives with the given bounding boxes. Primitives are referred
+    // to by their index in the list.
+    pub fn new(bounds: &[BoundingBox]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            primitives: (0..bounds.len()).collect(),
+        };
+
+        if !bounds.is_empty() {
+            let count = bounds.len();
+            bvh.build(bounds, 0, count);
+        }
+
+        bvh
+    }
+
+    fn build(&mut self, bounds: &[BoundingBox], first: usize, count: usize) {
+        let node_bounds = self.primitives[first..first + count]
+            .iter()
+            .fold(BoundingBox::empty(), |total, &i| total.union(&bounds[i]));
+
+        if count <= MAX_LEAF_SIZE {
+            self.nodes.push(Node::Leaf {
+                                bounds: node_bounds,
+                                first,
+                                count,
+                            });
+            return;
+        }
+
+        // Split the primitives in half along the axis where their centers are spread out the most.
+        let center_bounds = self.primitives[first..first + count]
+            .iter()
+            .fold(BoundingBox::empty(), |total, &i| total.grow(&bounds[i].center()));
+        let axis = center_bounds.longest_axis();
+
+        self.primitives[first..first + count].sort_by(|&a, &b| {
+            let a = bounds[a].center()[axis];
+            let b = bounds[b].center()[axis];
+            a.partial_cmp(&b).unwrap_or(::std::cmp::Ordering::Equal)
+        });
+
+        let node_index = self.nodes.len();
+        self.nodes.push(Node::Interior {
+                            bounds: node_bounds,
+                            second_child: 0,
+                        });
+
+        let half = count / 2;
+        self.build(bounds, first, half);
+
+        let second_child_index = self.nodes.len();
+        if let Node::Interior { ref mut second_child, .. } = self.nodes[node_index] {
+            *second_child = second_child_index;
+        }
+        self.build(bounds, first + half, count - half);
+    }
+
+    // Finds the closest primitive that the ray hits, using intersect to get the distance to each
+    // primitive that might be hit. Returns the distance and the primitive's index.
+    pub fn intersect<F>(&self, ray: &Ray, intersect: F) -> Option<(f64, usize)>
+        where F: Fn(usize) -> Option<f64>
+    {
+        let mut closest: Option<(f64, usize)> = None;
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            match node.bounds().intersect(ray) {
+                Some(distance) => {
+                    if let Some((closest_distance, _)) = closest {
+                        if distance > closest_distance {
+                            continue;
+                        }
+                    }
+                }
+                None => continue,
+            }
+
+            match *node {
+                Node::Leaf { first, count, .. } => {
+                    for &primitive in &self.primitives[first..first + count] {
+                        if let Some(distance) = intersect(primitive) {
+                            let is_closer = match closest {
+                                Some((closest_distance, _)) => distance < closest_distance,
+                                None => true,
+                            };
+                            if is_closer {
+                                closest = Some((distance, primitive));
+                            }
+                        }
+                    }
+                }
+                Node::Interior { second_child, .. } => {
+                    stack.push(second_child);
+                    stack.push(node_index + 1);
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+#[test]
+fn it_finds_the_closest_primitive() {
+    use super::super::Vector3;
+
+    // A row of unit boxes along the Z axis, each standing in for a primitive that is hit at the
+    // front of its box.
+    let bounds: Vec<BoundingBox> = (0..20)
+        .map(|i| {
+                 let z = -(i as f64) * 2.0;
+                 BoundingBox {
+                     min: Point3::new(-0.5, -0.5, z - 1.0),
+                     max: Point3::new(0.5, 0.5, z),
+                 }
+             })
+        .collect();
+    let bvh = Bvh::new(&bounds);
+
+    let ray = Ray::new(Point3::new(0.0, 0.0, 10.0), -Vector3::unit_z());
+    let hit = bvh.intersect(&ray, |i| bounds[i].intersect(&ray));
+    assert_eq!(hit, Some((10.0, 0)));
+
+    let ray = Ray::new(Point3::new(0.0, 0.0, -14.5), -Vector3::unit_z());
+    let hit = bvh.intersect(&ray, |i| bounds[i].intersect(&ray));
+    assert_eq!(hit, Some((0.0, 7)));
+
+    let ray = Ray::new(Point3::new(2.0, 0.0, 10.0), -Vector3::unit_z());
+    assert_eq!(bvh.intersect(&ray, |i| bounds[i].intersect(&ray)), None);
+}
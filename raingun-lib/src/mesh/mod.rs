@@ -0,0 +1,166 @@
+use serde;
+use std::fmt;
+use std::path::Path;
+
+use bodies::{intersect_triangle, triangle_surface_normal, triangle_texture_coords};
+use cgmath::{Matrix4, SquareMatrix};
+use cgmath::Transform as MatrixTransform;
+use cgmath::prelude::*;
+use material::{Material, TextureCoords};
+use ray::Ray;
+use transform::Transform;
+use super::{Point3, Vector3};
+
+mod bvh;
+mod obj;
+mod ply;
+mod stl;
+
+use self::bvh::{BoundingBox, Bvh};
+
+// A single triangle of a mesh. The front side is the one where its vertices are in
+// counter-clockwise order.
+#[derive(Debug, Clone)]
+pub struct Face {
+    pub vertices: [Point3; 3],
+    pub normals: Option<[Vector3; 3]>,
+    pub texture_coords: Option<[TextureCoords; 3]>,
+    // Index into the mesh's own materials, or None for the material given in the scene.
+    pub material: Option<usize>,
+}
+
+impl Face {
+    pub fn surface_normal(&self, hit_point: &Point3) -> Vector3 {
+        triangle_surface_normal(&self.vertices, &self.normals, hit_point)
+    }
+
+    pub fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
+        triangle_texture_coords(&self.vertices, &self.texture_coords, hit_point)
+    }
+
+    fn transform(&mut self, matrix: &Matrix4<f64>, normal_matrix: &Matrix4<f64>) {
+        for vertex in &mut self.vertices {
+            *vertex = matrix.transform_point(*vertex);
+        }
+
+        if let Some(ref mut normals) = self.normals {
+            for normal in normals.iter_mut() {
+                *normal = normal_matrix.transform_vector(*normal).normalize();
+            }
+        }
+
+        // Mirroring turns counter-clockwise vertices clockwise, so swap two of them to keep the
+        // front side facing the same way.
+        if matrix.determinant() < 0.0 {
+            self.vertices.swap(1, 2);
+            if let Some(ref mut normals) = self.normals {
+                normals.swap(1, 2);
+            }
+            if let Some(ref mut texture_coords) = self.texture_coords {
+                texture_coords.swap(1, 2);
+            }
+        }
+    }
+}
+
+// A body made of triangles, loaded from a file.
+#[derive(Clone)]
+pub struct Mesh {
+    faces: Vec<Face>,
+    // Materials that came with the mesh file.
+    materials: Vec<Material>,
+    // Used for faces that don't have a material of their own.
+    material: Material,
+    bvh: Bvh,
+}
+
+// How a mesh is described in the scene file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDefinition {
+    path: String,
+    #[serde(default)]
+    transform: Transform,
+    material: Material,
+}
+
+impl Mesh {
+    pub fn new(faces: Vec<Face>, materials: Vec<Material>, material: Material) -> Mesh {
+        let bounds: Vec<BoundingBox> = faces
+            .iter()
+            .map(|face| BoundingBox::from_points(&face.vertices))
+            .collect();
+
+        Mesh {
+            bvh: Bvh::new(&bounds),
+            faces,
+            materials,
+            material,
+        }
+    }
+
+    // Loads a mesh file and places it in the scene with the transform. The format is decided by
+    // the file's extension: Wavefront OBJ, PLY or binary STL. Faces without a material of their
+    // own from an OBJ file's materials use the given material.
+    pub fn load(path: &Path, transform: &Transform, material: Material) -> Result<Mesh, String> {
+        let extension = path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        let (mut faces, materials) = match extension.as_deref() {
+            Some("obj") => obj::load(path, &material)?,
+            Some("ply") => (ply::load(path)?, Vec::new()),
+            Some("stl") => (stl::load(path)?, Vec::new()),
+            _ => return Err(String::from("Unsupported mesh format, expected .obj, .ply or .stl")),
+        };
+
+        let matrix = transform.matrix();
+        let normal_matrix = transform.normal_matrix();
+        for face in &mut faces {
+            face.transform(&matrix, &normal_matrix);
+        }
+
+        Ok(Mesh::new(faces, materials, material))
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    pub fn face_material(&self, face: &Face) -> &Material {
+        match face.material {
+            Some(index) => &self.materials[index],
+            None => &self.material,
+        }
+    }
+
+    // Finds the closest face that the ray hits.
+    pub fn intersect(&self, ray: &Ray) -> Option<(f64, &Face)> {
+        self.bvh
+            .intersect(ray, |i| intersect_triangle(&self.faces[i].vertices, ray))
+            .map(|(distance, i)| (distance, &self.faces[i]))
+    }
+}
+
+impl serde::Deserialize for Mesh {
+    fn deserialize<D>(deserializer: D) -> Result<Mesh, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let MeshDefinition { path, transform, material } =
+            MeshDefinition::deserialize(deserializer)?;
+        Mesh::load(Path::new(&path), &transform, material).map_err(|err| {
+            Error::custom(format!("Could not load mesh file {}: {}", path, err))
+        })
+    }
+}
+
+impl fmt::Debug for Mesh {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "Mesh with {} triangles and {} materials",
+               self.faces.len(),
+               self.materials.len())
+    }
+}
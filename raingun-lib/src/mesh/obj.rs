@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use image;
+
+use color::Color;
+use material::{Coloration, Material, Surface, Texture, TextureCoords};
+use super::Face;
+use super::super::{Point3, Vector3};
+
+// Loads the triangles of a Wavefront OBJ file, along with the materials from the MTL files that it
+// refers to. MTL files have no albedo, so their materials get the albedo of the default material.
+pub fn load(path: &Path,
+            default_material: &Material)
+            -> Result<(Vec<Face>, Vec<Material>), String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    parse(BufReader::new(file), |library| {
+        let library_path = directory.join(library);
+        load_materials(&library_path, default_material)
+            .map_err(|err| format!("{}: {}", library_path.to_string_lossy(), err))
+    })
+}
+
+fn load_materials(path: &Path,
+                  default_material: &Material)
+                  -> Result<Vec<(String, Material)>, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    parse_materials(BufReader::new(file), directory, default_material)
+}
+
+// Parses OBJ data. load_library is called with the name of every MTL file the data refers to.
+fn parse<R, F>(reader: R, mut load_library: F) -> Result<(Vec<Face>, Vec<Material>), String>
+    where R: BufRead,
+          F: FnMut(&str) -> Result<Vec<(String, Material)>, String>
+{
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut texture_coords: Vec<TextureCoords> = Vec::new();
+    let mut normals: Vec<Vector3> = Vec::new();
+
+    let mut materials: Vec<Material> = Vec::new();
+    let mut material_indices: HashMap<String, usize> = HashMap::new();
+    let mut current_material = None;
+
+    let mut faces = Vec::new();
+
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| err.to_string())?;
+        let at_line = |message: String| format!("line {}: {}", line_index + 1, message);
+
+        let mut values = content(&line).split_whitespace();
+        let keyword = match values.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let values: Vec<&str> = values.collect();
+
+        match keyword {
+            "v" => {
+                let v = parse_numbers(&values, 3).map_err(&at_line)?;
+                positions.push(Point3::new(v[0], v[1], v[2]));
+            }
+            "vt" => {
+                let v = parse_numbers(&values, 1).map_err(&at_line)?;
+                // OBJ puts the origin in the bottom left corner, while textures start at the top.
+                texture_coords.push(TextureCoords {
+                                        x: v[0] as f32,
+                                        y: 1.0 - v.get(1).cloned().unwrap_or(0.0) as f32,
+                                    });
+            }
+            "vn" => {
+                let v = parse_numbers(&values, 3).map_err(&at_line)?;
+                normals.push(Vector3::new(v[0], v[1], v[2]));
+            }
+            "f" => {
+                if values.len() < 3 {
+                    return Err(at_line(String::from("Faces need at least three vertices")));
+                }
+
+                let mut vertices = Vec::with_capacity(values.len());
+                for value in &values {
+                    let vertex = parse_vertex(value,
+                                              positions.len(),
+                                              texture_coords.len(),
+                                              normals.len())
+                            .map_err(&at_line)?;
+                    vertices.push(vertex);
+                }
+
+                // Polygons are split into a fan of triangles around the first vertex.
+                for i in 1..vertices.len() - 1 {
+                    let (a, b, c) = (vertices[0], vertices[i], vertices[i + 1]);
+
+                    let face_texture_coords = match (a.1, b.1, c.1) {
+                        (Some(a), Some(b), Some(c)) => {
+                            Some([texture_coords[a], texture_coords[b], texture_coords[c]])
+                        }
+                        _ => None,
+                    };
+                    let face_normals = match (a.2, b.2, c.2) {
+                        (Some(a), Some(b), Some(c)) => Some([normals[a], normals[b], normals[c]]),
+                        _ => None,
+                    };
+
+                    faces.push(Face {
+                                   vertices: [positions[a.0], positions[b.0], positions[c.0]],
+                                   normals: face_normals,
+                                   texture_coords: face_texture_coords,
+                                   material: current_material,
+                               });
+                }
+            }
+            "mtllib" => {
+                for library in &values {
+                    for (name, material) in load_library(library)? {
+                        material_indices.insert(name, materials.len());
+                        materials.push(material);
+                    }
+                }
+            }
+            "usemtl" => {
+                // Unknown materials fall back to the default material.
+                current_material = values
+                    .first()
+                    .and_then(|name| material_indices.get(*name))
+                    .cloned();
+            }
+            // Objects, groups, smoothing groups, lines and so on don't matter for rendering.
+            _ => {}
+        }
+    }
+
+    Ok((faces, materials))
+}
+
+// Material properties from an MTL file.
+struct MtlMaterial {
+    name: String,
+    diffuse: Color,
+    specular: Color,
+    diffuse_map: Option<String>,
+    illumination: u32,
+    dissolve: f32,
+    refraction_index: Option<f32>,
+}
+
+impl MtlMaterial {
+    fn new(name: &str) -> MtlMaterial {
+        MtlMaterial {
+            name: String::from(name),
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::black(),
+            diffuse_map: None,
+            illumination: 2,
+            dissolve: 1.0,
+            refraction_index: None,
+        }
+    }
+
+    fn to_material(&self,
+                   directory: &Path,
+                   default_material: &Material)
+                   -> Result<Material, String> {
+        let coloration = match self.diffuse_map {
+            Some(ref file) => {
+                let path = directory.join(file);
+                let image = image::open(&path).map_err(|err| {
+                                 format!("Could not load texture file {}: {}",
+                                         path.to_string_lossy(),
+                                         err)
+                             })?;
+                Coloration::Texture(Texture {
+                                        image,
+                                        x_offset: 0.0,
+                                        y_offset: 0.0,
+                                    })
+            }
+            None => Coloration::Color(self.diffuse),
+        };
+
+        let reflectivity = self.specular
+            .red
+            .max(self.specular.green)
+            .max(self.specular.blue)
+            .min(1.0);
+
+        let surface = if self.dissolve < 1.0 {
+            Surface::Refractive {
+                index: self.refraction_index.unwrap_or(1.5),
+                transparency: 1.0 - self.dissolve,
+            }
+        } else if (self.illumination == 3 || self.illumination == 5) && reflectivity > 0.0 {
+            // Illumination models with ray traced reflections
+            Surface::Reflecting { reflectivity }
+        } else {
+            Surface::Diffuse
+        };
+
+        Ok(Material {
+               coloration,
+               albedo: default_material.albedo,
+               surface,
+           })
+    }
+}
+
+// Parses MTL data. Texture files are looked up relative to the directory.
+fn parse_materials<R>(reader: R,
+                      directory: &Path,
+                      default_material: &Material)
+                      -> Result<Vec<(String, Material)>, String>
+    where R: BufRead
+{
+    let mut parsed: Vec<MtlMaterial> = Vec::new();
+
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| err.to_string())?;
+        let at_line = |message: String| format!("line {}: {}", line_index + 1, message);
+
+        let mut values = content(&line).split_whitespace();
+        let keyword = match values.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let values: Vec<&str> = values.collect();
+
+        if keyword == "newmtl" {
+            let name = values.join(" ");
+            parsed.push(MtlMaterial::new(&name));
+            continue;
+        }
+
+        let material = match parsed.last_mut() {
+            Some(material) => material,
+            None => return Err(at_line(format!("{} before the first newmtl", keyword))),
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = parse_color(&values).map_err(&at_line)?,
+            "Ks" => material.specular = parse_color(&values).map_err(&at_line)?,
+            "d" => material.dissolve = parse_numbers(&values, 1).map_err(&at_line)?[0] as f32,
+            "Tr" => {
+                material.dissolve = 1.0 - parse_numbers(&values, 1).map_err(&at_line)?[0] as f32
+            }
+            "Ni" => {
+                material.refraction_index =
+                    Some(parse_numbers(&values, 1).map_err(&at_line)?[0] as f32)
+            }
+            "illum" => {
+                material.illumination = parse_numbers(&values, 1).map_err(&at_line)?[0] as u32
+            }
+            // Options like -s or -o come before the file name, which is last.
+            "map_Kd" => material.diffuse_map = values.last().map(|file| String::from(*file)),
+            _ => {}
+        }
+    }
+
+    let mut materials = Vec::with_capacity(parsed.len());
+    for material in parsed {
+        materials.push((material.name.clone(), material.to_material(directory, default_material)?));
+    }
+
+    Ok(materials)
+}
+
+// The part of the line before any comment.
+fn content(line: &str) -> &str {
+    match line.find('#') {
+        Some(index) => &line[..index],
+        None => line,
+    }
+}
+
+fn parse_numbers(values: &[&str], required: usize) -> Result<Vec<f64>, String> {
+    if values.len() < required {
+        return Err(format!("Expected {} numbers, found {}", required, values.len()));
+    }
+
+    values
+        .iter()
+        .map(|value| f64::from_str(value).map_err(|_| format!("Invalid number {}", value)))
+        .collect()
+}
+
+fn parse_color(values: &[&str]) -> Result<Color, String> {
+    let v = parse_numbers(values, 3)?;
+    Ok(Color::new(v[0] as f32, v[1] as f32, v[2] as f32))
+}
+
+// Parses a face vertex like 1, 1/2, 1//3 or 1/2/3 into indices of its position, texture
+// coordinates and normal.
+fn parse_vertex(value: &str,
+                positions: usize,
+                texture_coords: usize,
+                normals: usize)
+                -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = value.split('/');
+
+    let position = match parts.next() {
+        Some(index) => resolve_index(index, positions, "Vertex")?,
+        None => return Err(format!("Invalid face vertex {}", value)),
+    };
+
+    let texture_coord = match parts.next() {
+        Some(index) if !index.is_empty() => {
+            Some(resolve_index(index, texture_coords, "Texture coordinate")?)
+        }
+        _ => None,
+    };
+
+    let normal = match parts.next() {
+        Some(index) if !index.is_empty() => Some(resolve_index(index, normals, "Normal")?),
+        _ => None,
+    };
+
+    Ok((position, texture_coord, normal))
+}
+
+// OBJ indices start at 1, and negative indices count backwards from the last element so far.
+fn resolve_index(index: &str, count: usize, kind: &str) -> Result<usize, String> {
+    let parsed = i64::from_str(index).map_err(|_| format!("Invalid {} index {}", kind, index))?;
+
+    if parsed > 0 && parsed as usize <= count {
+        Ok(parsed as usize - 1)
+    } else if parsed < 0 && (-parsed) as usize <= count {
+        Ok((count as i64 + parsed) as usize)
+    } else {
+        Err(format!("{} index {} is out of range", kind, index))
+    }
+}
+
+#[test]
+fn it_triangulates_polygons() {
+    let obj = "# A unit square
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 1
+vn 0 0 1
+f 1/1/1 2/1/1 3/2/1 4/2/1
+f -4 -3 -2
+";
+    let (faces, materials) = parse(obj.as_bytes(), |_| Ok(Vec::new())).unwrap();
+
+    assert_eq!(faces.len(), 3);
+    assert_eq!(materials.len(), 0);
+    assert_eq!(faces[1].vertices,
+               [Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 1.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0)]);
+    assert_eq!(faces[1].texture_coords.unwrap()[2].y, 0.0);
+    assert_eq!(faces[1].normals, Some([Vector3::unit_z(); 3]));
+    assert!(faces[2].normals.is_none());
+    assert!(faces[2].texture_coords.is_none());
+}
+
+#[test]
+fn it_reports_invalid_indices() {
+    let obj = "v 0 0 0\nv 1 0 0\nf 1 2 3\n";
+    let error = parse(obj.as_bytes(), |_| Ok(Vec::new())).unwrap_err();
+    assert_eq!(error, "line 3: Vertex index 3 is out of range");
+}
+
+#[test]
+fn it_uses_materials_from_libraries() {
+    use material::test_material;
+
+    let mtl = "newmtl red
+Kd 1 0 0
+newmtl mirror
+Kd 1 1 1
+Ks 0.8 0.8 0.8
+illum 3
+newmtl glass
+d 0.25
+Ni 1.33
+";
+    let default_material = Material { albedo: 0.5, ..test_material() };
+    let library = parse_materials(mtl.as_bytes(), Path::new(""), &default_material).unwrap();
+
+    let obj = "mtllib test.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+usemtl mirror
+f 1 2 3
+usemtl unknown
+f 1 2 3
+";
+    let (faces, materials) = parse(obj.as_bytes(), |name| {
+        assert_eq!(name, "test.mtl");
+        Ok(library.clone())
+    })
+            .unwrap();
+
+    assert_eq!(materials.len(), 3);
+    assert_eq!(faces[0].material, Some(1));
+    assert_eq!(faces[1].material, None);
+
+    match materials[1].surface {
+        Surface::Reflecting { reflectivity } => assert!((reflectivity - 0.8).abs() < 1e-6),
+        surface => panic!("Unexpected surface {:?}", surface),
+    }
+    match materials[2].surface {
+        Surface::Refractive { index, transparency } => {
+            assert_eq!((index, transparency), (1.33, 0.75))
+        }
+        surface => panic!("Unexpected surface {:?}", surface),
+    }
+    assert_eq!(materials[0].albedo, 0.5);
+}
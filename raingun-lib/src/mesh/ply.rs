@@ -0,0 +1,425 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use material::TextureCoords;
+use super::Face;
+use super::super::{Point3, Vector3};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar { name: String, kind: Kind },
+    List {
+        name: String,
+        count_kind: Kind,
+        item_kind: Kind,
+    },
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// Loads the triangles of a PLY file, in either ASCII or binary little-endian format. Positions,
+// normals and texture coordinates are read from the vertices, and faces with more than three
+// vertices are split into triangles.
+pub fn load(path: &Path) -> Result<Vec<Face>, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    parse(BufReader::new(file))
+}
+
+fn parse<R: BufRead>(mut reader: R) -> Result<Vec<Face>, String> {
+    let (format, elements) = parse_header(&mut reader)?;
+
+    match format {
+        Format::Ascii => {
+            let mut body = String::new();
+            reader
+                .read_to_string(&mut body)
+                .map_err(|err| err.to_string())?;
+
+            let mut values = body.split_whitespace();
+            read_elements(&elements, |kind| {
+                let value = match values.next() {
+                    Some(value) => value,
+                    None => return Err(String::from("Unexpected end of file")),
+                };
+                parse_value(value, kind)
+            })
+        }
+        Format::BinaryLittleEndian => {
+            read_elements(&elements, |kind| read_binary_value(&mut reader, kind))
+        }
+    }
+}
+
+fn parse_header<R: BufRead>(reader: &mut R) -> Result<(Format, Vec<Element>), String> {
+    let mut line = String::new();
+    let mut line_number = 0;
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line).map_err(|err| err.to_string())?;
+        line_number += 1;
+
+        if read == 0 {
+            return Err(String::from("Unexpected end of file in header"));
+        }
+
+        let values: Vec<&str> = line.split_whitespace().collect();
+        let at_line = |message: String| format!("line {}: {}", line_number, message);
+
+        if line_number == 1 {
+            if values != ["ply"] {
+                return Err(String::from("Not a PLY file"));
+            }
+            continue;
+        }
+
+        match values.first().copied() {
+            Some("format") => {
+                format = match values.get(1).copied() {
+                    Some("ascii") => Some(Format::Ascii),
+                    Some("binary_little_endian") => Some(Format::BinaryLittleEndian),
+                    Some(other) => {
+                        return Err(at_line(format!("Unsupported format {}", other)));
+                    }
+                    None => return Err(at_line(String::from("Missing format"))),
+                };
+            }
+            Some("element") => {
+                if values.len() != 3 {
+                    return Err(at_line(String::from("Expected element name and count")));
+                }
+                let count = usize::from_str(values[2])
+                    .map_err(|_| at_line(format!("Invalid element count {}", values[2])))?;
+
+                elements.push(Element {
+                                  name: String::from(values[1]),
+                                  count,
+                                  properties: Vec::new(),
+                              });
+            }
+            Some("property") => {
+                let property = parse_property(&values[1..]).map_err(&at_line)?;
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(property),
+                    None => return Err(at_line(String::from("Property outside of an element"))),
+                }
+            }
+            Some("end_header") => break,
+            // Comments and obj_info don't matter.
+            _ => {}
+        }
+    }
+
+    match format {
+        Some(format) => Ok((format, elements)),
+        None => Err(String::from("Missing format in header")),
+    }
+}
+
+fn parse_property(values: &[&str]) -> Result<Property, String> {
+    match values.len() {
+        2 => {
+            Ok(Property::Scalar {
+                   kind: parse_kind(values[0])?,
+                   name: String::from(values[1]),
+               })
+        }
+        4 if values[0] == "list" => {
+            Ok(Property::List {
+                   count_kind: parse_kind(values[1])?,
+                   item_kind: parse_kind(values[2])?,
+                   name: String::from(values[3]),
+               })
+        }
+        _ => Err(format!("Invalid property {}", values.join(" "))),
+    }
+}
+
+fn parse_kind(kind: &str) -> Result<Kind, String> {
+    match kind {
+        "char" | "int8" => Ok(Kind::Int8),
+        "uchar" | "uint8" => Ok(Kind::UInt8),
+        "short" | "int16" => Ok(Kind::Int16),
+        "ushort" | "uint16" => Ok(Kind::UInt16),
+        "int" | "int32" => Ok(Kind::Int32),
+        "uint" | "uint32" => Ok(Kind::UInt32),
+        "float" | "float32" => Ok(Kind::Float32),
+        "double" | "float64" => Ok(Kind::Float64),
+        _ => Err(format!("Unknown property type {}", kind)),
+    }
+}
+
+fn parse_value(value: &str, kind: Kind) -> Result<f64, String> {
+    let parsed = match kind {
+        Kind::Float32 | Kind::Float64 => f64::from_str(value).ok(),
+        _ => i64::from_str(value).ok().map(|value| value as f64),
+    };
+
+    parsed.ok_or_else(|| format!("Invalid {:?} value {}", kind, value))
+}
+
+fn read_binary_value<R: Read>(reader: &mut R, kind: Kind) -> Result<f64, String> {
+    let value = match kind {
+        Kind::Int8 => reader.read_i8().map(|value| value as f64),
+        Kind::UInt8 => reader.read_u8().map(|value| value as f64),
+        Kind::Int16 => reader.read_i16::<LittleEndian>().map(|value| value as f64),
+        Kind::UInt16 => reader.read_u16::<LittleEndian>().map(|value| value as f64),
+        Kind::Int32 => reader.read_i32::<LittleEndian>().map(|value| value as f64),
+        Kind::UInt32 => reader.read_u32::<LittleEndian>().map(|value| value as f64),
+        Kind::Float32 => reader.read_f32::<LittleEndian>().map(|value| value as f64),
+        Kind::Float64 => reader.read_f64::<LittleEndian>(),
+    };
+
+    value.map_err(|err| if err.kind() == ErrorKind::UnexpectedEof {
+                      String::from("Unexpected end of file")
+                  } else {
+                      err.to_string()
+                  })
+}
+
+// Reads all elements with read_value, which returns the next value of the given kind. Vertices
+// and faces are kept, everything else is skipped.
+fn read_elements<F>(elements: &[Element], mut read_value: F) -> Result<Vec<Face>, String>
+    where F: FnMut(Kind) -> Result<f64, String>
+{
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut normals: Vec<Vector3> = Vec::new();
+    let mut texture_coords: Vec<TextureCoords> = Vec::new();
+    let mut faces = Vec::new();
+
+    for element in elements {
+        for number in 0..element.count {
+            let mut scalars: Vec<(&str, f64)> = Vec::new();
+            let mut indices: Vec<f64> = Vec::new();
+
+            for property in &element.properties {
+                match *property {
+                    Property::Scalar { ref name, kind } => {
+                        scalars.push((name.as_str(), read_value(kind)?));
+                    }
+                    Property::List {
+                        ref name,
+                        count_kind,
+                        item_kind,
+                    } => {
+                        let count = read_value(count_kind)? as usize;
+                        let is_vertex_list = name == "vertex_indices" || name == "vertex_index";
+
+                        for _ in 0..count {
+                            let value = read_value(item_kind)?;
+                            if is_vertex_list {
+                                indices.push(value);
+                            }
+                        }
+                    }
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => {
+                    let get = |names: &[&str]| {
+                        scalars
+                            .iter()
+                            .find(|&&(name, _)| names.contains(&name))
+                            .map(|&(_, value)| value)
+                    };
+
+                    positions.push(Point3::new(get(&["x"]).unwrap_or(0.0),
+                                               get(&["y"]).unwrap_or(0.0),
+                                               get(&["z"]).unwrap_or(0.0)));
+
+                    let normal = (get(&["nx"]), get(&["ny"]), get(&["nz"]));
+                    if let (Some(x), Some(y), Some(z)) = normal {
+                        normals.push(Vector3::new(x, y, z));
+                    }
+
+                    let u = get(&["u", "s", "texture_u", "texture_s"]);
+                    let v = get(&["v", "t", "texture_v", "texture_t"]);
+                    if let (Some(u), Some(v)) = (u, v) {
+                        // Like in OBJ files, texture coordinates start in the bottom left corner.
+                        texture_coords.push(TextureCoords {
+                                                x: u as f32,
+                                                y: 1.0 - v as f32,
+                                            });
+                    }
+                }
+                "face" => {
+                    if indices.len() < 3 {
+                        return Err(format!("Face {} has less than three vertices", number));
+                    }
+
+                    let indices = indices
+                        .iter()
+                        .map(|&index| if index < 0.0 || index >= positions.len() as f64 {
+                                 Err(format!("Face {} has vertex index {}, which is out of range",
+                                             number,
+                                             index))
+                             } else {
+                                 Ok(index as usize)
+                             })
+                        .collect::<Result<Vec<usize>, String>>()?;
+
+                    // Only use normals and texture coordinates when every vertex has them.
+                    let has_normals = normals.len() == positions.len();
+                    let has_texture_coords = texture_coords.len() == positions.len();
+
+                    // Polygons are split into a fan of triangles around the first vertex.
+                    for i in 1..indices.len() - 1 {
+                        let (a, b, c) = (indices[0], indices[i], indices[i + 1]);
+                        faces.push(Face {
+                                       vertices: [positions[a], positions[b], positions[c]],
+                                       normals: if has_normals {
+                                           Some([normals[a], normals[b], normals[c]])
+                                       } else {
+                                           None
+                                       },
+                                       texture_coords: if has_texture_coords {
+                                           Some([texture_coords[a],
+                                                 texture_coords[b],
+                                                 texture_coords[c]])
+                                       } else {
+                                           None
+                                       },
+                                       material: None,
+                                   });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(faces)
+}
+
+#[test]
+fn it_reads_ascii_files() {
+    let ply = "ply
+format ascii 1.0
+comment A unit square
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0 1
+1 0 0 0 0 1
+1 1 0 0 0 1
+0 1 0 0 0 1
+4 0 1 2 3
+";
+    let faces = parse(ply.as_bytes()).unwrap();
+
+    assert_eq!(faces.len(), 2);
+    assert_eq!(faces[1].vertices,
+               [Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 1.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0)]);
+    assert_eq!(faces[1].normals, Some([Vector3::unit_z(); 3]));
+    assert!(faces[1].texture_coords.is_none());
+}
+
+#[test]
+fn it_reads_binary_little_endian_files() {
+    use byteorder::WriteBytesExt;
+
+    let mut ply = Vec::new();
+    ply.extend_from_slice(b"ply
+format binary_little_endian 1.0
+element vertex 3
+property double x
+property double y
+property double z
+property uchar red
+element face 1
+property list uchar uint vertex_indices
+end_header
+");
+    for &(x, y) in &[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)] {
+        ply.write_f64::<LittleEndian>(x).unwrap();
+        ply.write_f64::<LittleEndian>(y).unwrap();
+        ply.write_f64::<LittleEndian>(-2.0).unwrap();
+        ply.write_u8(255).unwrap();
+    }
+    ply.write_u8(3).unwrap();
+    for &index in &[0, 1, 2] {
+        ply.write_u32::<LittleEndian>(index).unwrap();
+    }
+
+    let faces = parse(&ply[..]).unwrap();
+    assert_eq!(faces.len(), 1);
+    assert_eq!(faces[0].vertices[2], Point3::new(0.0, 1.0, -2.0));
+    assert!(faces[0].normals.is_none());
+}
+
+#[test]
+fn it_reports_truncated_files() {
+    let ply = "ply
+format ascii 1.0
+element vertex 2
+property float x
+end_header
+1.0
+";
+    assert_eq!(parse(ply.as_bytes()).unwrap_err(), "Unexpected end of file");
+}
+
+#[test]
+fn it_reports_vertex_indices_out_of_range_by_face() {
+    let header = "ply
+format ascii 1.0
+element vertex 4
+property float x
+property float y
+property float z
+element face 2
+property list uchar int vertex_indices
+end_header
+0 0 0
+1 0 0
+1 1 0
+0 1 0
+4 0 1 2 3
+";
+    let negative = format!("{}3 0 -1 2\n", header);
+    assert_eq!(parse(negative.as_bytes()).unwrap_err(),
+               "Face 1 has vertex index -1, which is out of range");
+
+    let too_large = format!("{}3 0 4 2\n", header);
+    assert_eq!(parse(too_large.as_bytes()).unwrap_err(),
+               "Face 1 has vertex index 4, which is out of range");
+}
@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::Face;
+use super::super::Point3;
+
+const HEADER_SIZE: usize = 80;
+// Normal, three vertices and an attribute byte count
+const TRIANGLE_SIZE: usize = 12 * 4 + 2;
+
+// Loads the triangles of a binary STL file. The normals stored in the file are ignored, as they
+// are often missing. The triangles' vertices are used instead, which are in counter-clockwise
+// order when seen from the outside.
+pub fn load(path: &Path) -> Result<Vec<Face>, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    parse(BufReader::new(file))
+}
+
+fn parse<R: Read>(mut reader: R) -> Result<Vec<Face>, String> {
+    let mut header = [0; HEADER_SIZE];
+    reader.read_exact(&mut header).map_err(read_error)?;
+
+    let count = reader.read_u32::<LittleEndian>().map_err(read_error)? as usize;
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).map_err(|err| err.to_string())?;
+
+    if data.len() < count * TRIANGLE_SIZE {
+        // ASCII files start with "solid", but so do some binary files, so only check this when
+        // the file doesn't make sense as a binary file.
+        if header.starts_with(b"solid") {
+            return Err(String::from("ASCII STL files are not supported, only binary ones"));
+        }
+        return Err(format!("Expected {} triangles, but the file ends after {}",
+                           count,
+                           data.len() / TRIANGLE_SIZE));
+    }
+
+    let mut faces = Vec::with_capacity(count);
+    for triangle in data.chunks(TRIANGLE_SIZE).take(count) {
+        // Skip the normal
+        let mut values = &triangle[12..];
+        let mut vertices = [Point3::new(0.0, 0.0, 0.0); 3];
+
+        for vertex in &mut vertices {
+            let x = values.read_f32::<LittleEndian>().map_err(read_error)?;
+            let y = values.read_f32::<LittleEndian>().map_err(read_error)?;
+            let z = values.read_f32::<LittleEndian>().map_err(read_error)?;
+            *vertex = Point3::new(x as f64, y as f64, z as f64);
+        }
+
+        faces.push(Face {
+                       vertices,
+                       normals: None,
+                       texture_coords: None,
+                       material: None,
+                   });
+    }
+
+    Ok(faces)
+}
+
+fn read_error(err: ::std::io::Error) -> String {
+    if err.kind() == ErrorKind::UnexpectedEof {
+        String::from("Unexpected end of file")
+    } else {
+        err.to_string()
+    }
+}
+
+#[cfg(test)]
+fn write_stl(triangles: &[[f32; 9]]) -> Vec<u8> {
+    use byteorder::WriteBytesExt;
+
+    let mut stl = vec![0; HEADER_SIZE];
+    stl.write_u32::<LittleEndian>(triangles.len() as u32).unwrap();
+    for triangle in triangles {
+        for _ in 0..3 {
+            stl.write_f32::<LittleEndian>(0.0).unwrap();
+        }
+        for &value in triangle {
+            stl.write_f32::<LittleEndian>(value).unwrap();
+        }
+        stl.write_u16::<LittleEndian>(0).unwrap();
+    }
+    stl
+}
+
+#[test]
+fn it_reads_binary_files() {
+    let stl = write_stl(&[[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+                          [1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.5]]);
+    let faces = parse(&stl[..]).unwrap();
+
+    assert_eq!(faces.len(), 2);
+    assert_eq!(faces[1].vertices,
+               [Point3::new(1.0, 0.0, 0.0),
+                Point3::new(1.0, 1.0, 0.0),
+                Point3::new(0.0, 1.0, 0.5)]);
+}
+
+#[test]
+fn it_reports_truncated_files() {
+    let mut stl = write_stl(&[[0.0; 9], [0.0; 9]]);
+    stl.truncate(HEADER_SIZE + 4 + TRIANGLE_SIZE + 10);
+    assert_eq!(parse(&stl[..]).unwrap_err(),
+               "Expected 2 triangles, but the file ends after 1");
+
+    let mut ascii = b"solid cube".to_vec();
+    ascii.resize(200, b' ');
+    assert_eq!(parse(&ascii[..]).unwrap_err(),
+               "ASCII STL files are not supported, only binary ones");
+
+    assert_eq!(parse(&b"solid"[..]).unwrap_err(), "Unexpected end of file");
+}
@@ -1,4 +1,4 @@
-use scene::Scene;
+use camera::Camera;
 use super::{Point3, Vector3, SHADOW_BIAS};
 use cgmath::prelude::*;
 
@@ -34,23 +34,13 @@ impl Ray {
         }
     }
 
-    pub fn create_prime(x: u32, y: u32, scene: &Scene, width: u32, height: u32) -> Ray {
-        // Represent the camera's sensor with -1.0 at 0,0 and 1.0 at width,height.
-        // Then adjust for aspect ratio and FoV
-
-        // TODO: Support portrait images too
-        assert!(width >= height);
-        let aspect_ratio = width as f64 / height as f64;
-
-        let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
-
-        let sensor_x = (((x as f64 + 0.5) / width as f64) * 2.0 - 1.0) * aspect_ratio *
-                       fov_adjustment;
-        let sensor_y = (1.0 - ((y as f64 + 0.5) / height as f64) * 2.0) * fov_adjustment;
-
-        // Ray direction is straight into image (z = -1.0)
-        let direction = Vector3::new(sensor_x, sensor_y, -1.0).normalize();
-        Ray::new(Point3::origin(), direction)
+    pub fn create_prime(x: f64,
+                        y: f64,
+                        camera: &Camera,
+                        width: u32,
+                        height: u32)
+                        -> Option<Ray> {
+        camera.create_prime(x, y, width, height)
     }
 
     pub fn create_reflection(normal: Vector3, incident: Vector3, intersection: Point3) -> Ray {
@@ -1,9 +1,13 @@
+use std::ops::Range;
 use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 
-use image::{ImageBuffer, Rgba, Pixel};
+use image::{ImageBuffer, Rgba};
 
 use bodies::*;
+use camera::Camera;
 use color::Color;
+use film::Film;
 use material::*;
 use ray::Ray;
 use scene::Scene;
@@ -21,72 +25,428 @@ pub struct RenderedPixel {
     pub color: Color,
 }
 
-pub fn render_image(scene: &Scene, width: u32, height: u32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-    use rayon::prelude::*;
+struct Sample {
+    // Position on the image
+    x: f64,
+    y: f64,
+    color: Color,
+    // Identifies the body that the prime ray hit, if any.
+    body_id: Option<usize>,
+}
+
+pub struct RenderedPass {
+    // Starts at 1 for the first pass.
+    pub number: u32,
+    // Total number of samples per pixel so far. Pixels that stopped early because their noise was
+    // below the scene's noise_threshold, or that adaptive sampling didn't refine, have fewer.
+    pub samples_per_pixel: u32,
+    // Total number of samples taken for the whole image so far.
+    pub samples: u64,
+    pub image: ImageBuffer<Rgba<u8>, Vec<u8>>,
+}
+
+// Number of rows that are rendered before their samples are added to the film and reported.
+const BAND_ROWS: u32 = 8;
 
-    let raw_image: Vec<u8> = (0u32..width * height)
-        .into_par_iter()
-        .map(|i| {
-                 let y = i / width;
-                 let x = i - y * width;
-                 render_pixel(scene, x, y, width, height).rgba()
-             })
-        .flat_map(|pixel| pixel.channels().to_owned())
-        .collect();
+// Pixels need at least this many samples before their noise estimate is trusted.
+const MIN_NOISE_SAMPLES: u32 = 4;
 
-    ImageBuffer::from_raw(width, height, raw_image).unwrap()
+// Renders the image in a single pass, unless the scene can stop early. The rendered image is
+// returned as the last pass.
+pub fn render_image(scene: &Scene, camera: &Camera, width: u32, height: u32) -> RenderedPass {
+    if stops_early(scene) {
+        return render_image_progressive(scene, camera, width, height, |_| ());
+    }
+
+    render_film(scene, camera, width, height, |_, _| true)
 }
 
 pub fn render_image_stream(scene: &Scene,
+                           camera: &Camera,
                            width: u32,
                            height: u32,
                            channel_tx: Sender<RenderedPixel>)
-                           -> () {
-    use std::sync::Arc;
-    use parking_lot::Mutex;
+                           -> RenderedPass {
+    if stops_early(scene) {
+        return render_image_stream_progressive(scene, camera, width, height, channel_tx, |_| ());
+    }
+
+    render_film(scene,
+                camera,
+                width,
+                height,
+                |film, rows| send_rows(&channel_tx, film, rows))
+}
+
+pub fn render_image_progressive<P>(scene: &Scene,
+                                   camera: &Camera,
+                                   width: u32,
+                                   height: u32,
+                                   on_pass: P)
+                                   -> RenderedPass
+    where P: FnMut(&RenderedPass)
+{
+    render_film_progressive(scene, camera, width, height, |_, _| true, on_pass)
+}
+
+pub fn render_image_stream_progressive<P>(scene: &Scene,
+                                          camera: &Camera,
+                                          width: u32,
+                                          height: u32,
+                                          channel_tx: Sender<RenderedPixel>,
+                                          on_pass: P)
+                                          -> RenderedPass
+    where P: FnMut(&RenderedPass)
+{
+    render_film_progressive(scene,
+                            camera,
+                            width,
+                            height,
+                            |film, rows| send_rows(&channel_tx, film, rows),
+                            on_pass)
+}
+
+// Whether rendering can stop before samples_per_pixel is reached, which requires rendering in
+// passes.
+fn stops_early(scene: &Scene) -> bool {
+    scene.noise_threshold.is_some() || scene.time_limit.is_some()
+}
+
+// Sends the current color of every pixel in the rows. Returns false if the channel is closed.
+fn send_rows(channel_tx: &Sender<RenderedPixel>, film: &Film, rows: Range<u32>) -> bool {
+    for y in rows {
+        for x in 0..film.width {
+            let receipt = channel_tx.send(RenderedPixel {
+                                              x,
+                                              y,
+                                              color: film.color(x, y),
+                                          });
+
+            if receipt.is_err() {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+// Renders the image into a film, in a single pass. on_rows is called with the range of rows that
+// changed every time a band of pixels has been added to the film. Rendering stops early if on_rows
+// returns false.
+fn render_film<F>(scene: &Scene,
+                  camera: &Camera,
+                  width: u32,
+                  height: u32,
+                  mut on_rows: F)
+                  -> RenderedPass
+    where F: FnMut(&Film, Range<u32>) -> bool
+{
+    let mut film = Film::new(width, height, scene.filter);
+    let all_pixels: Vec<u32> = (0..width * height).collect();
+
+    let samples_per_pixel = match scene.adaptive_sampling {
+        None => {
+            render_pass(scene,
+                        camera,
+                        &mut film,
+                        &all_pixels,
+                        |_| scene.sampling_pattern.pixel_offsets(scene.samples_per_pixel),
+                        &mut on_rows);
+            scene.samples_per_pixel.max(1)
+        }
+        Some(ref adaptive) => {
+            // Render every pixel with a single sample first, then render more samples for pixels
+            // that differ from their neighbours.
+            let first_pass = match render_pass(scene,
+                                               camera,
+                                               &mut film,
+                                               &all_pixels,
+                                               |_| scene.sampling_pattern.pixel_offsets(1),
+                                               &mut on_rows) {
+                Some(samples) => samples,
+                None => return single_pass(&film, 1),
+            };
+
+            let refined_pixels: Vec<u32> = all_pixels
+                .iter()
+                .cloned()
+                .filter(|&i| needs_refinement(&first_pass, i, width, height, adaptive.threshold))
+                .collect();
+
+            // The refined pixels get the whole pattern for max_samples, except for the position
+            // that is closest to the sample they already have.
+            let max_samples = adaptive.max_samples.max(2);
+            let remaining_offsets = |i: u32| {
+                let first = &first_pass[i as usize];
+                let taken = (first.x - (i % width) as f64, first.y - (i / width) as f64);
+                scene.sampling_pattern.remaining_offsets(max_samples, taken)
+            };
+
+            render_pass(scene,
+                        camera,
+                        &mut film,
+                        &refined_pixels,
+                        remaining_offsets,
+                        &mut on_rows);
+
+            if refined_pixels.is_empty() { 1 } else { max_samples }
+        }
+    };
+
+    single_pass(&film, samples_per_pixel)
+}
+
+fn single_pass(film: &Film, samples_per_pixel: u32) -> RenderedPass {
+    RenderedPass {
+        number: 1,
+        samples_per_pixel,
+        samples: film.samples(),
+        image: film.to_image(),
+    }
+}
+
+// Renders the whole image in passes, each pass adding samples to every pixel, until the scene's
+// samples_per_pixel is reached. Each pass doubles the total number of samples, so an image of the
+// whole scene is available quickly and is then refined. on_pass is called after every pass, and
+// the last pass is returned.
+//
+// Rendering stops earlier when the scene's time_limit would be exceeded by the next pass, or when
+// the noise of every pixel is below the scene's noise_threshold. Pixels whose noise is below the
+// threshold don't get any more samples. The first pass is rendered even if the time is up.
+//
+// Adaptive sampling is not used for progressive rendering.
+fn render_film_progressive<F, P>(scene: &Scene,
+                                 camera: &Camera,
+                                 width: u32,
+                                 height: u32,
+                                 mut on_rows: F,
+                                 mut on_pass: P)
+                                 -> RenderedPass
+    where F: FnMut(&Film, Range<u32>) -> bool,
+          P: FnMut(&RenderedPass)
+{
+    let mut film = Film::new(width, height, scene.filter);
+    let mut pixels: Vec<u32> = (0..width * height).collect();
+
+    let target_samples = scene.samples_per_pixel.max(1);
+    let deadline = scene.time_limit.map(|seconds| Instant::now() + duration_from_seconds(seconds));
+
+    // Time it took to render one sample for one pixel in the last pass, used to predict how many
+    // samples fit in the time that is left.
+    let mut seconds_per_sample = 0.0;
+
+    let mut pass = RenderedPass {
+        number: 0,
+        samples_per_pixel: 0,
+        samples: 0,
+        image: film.to_image(),
+    };
+
+    while pass.samples_per_pixel < target_samples && !pixels.is_empty() {
+        let mut samples = pass.samples_per_pixel
+            .max(1)
+            .min(target_samples - pass.samples_per_pixel);
+
+        // The first pass is always rendered, so that there is an image even when the time is up
+        // before rendering starts.
+        if let Some(deadline) = deadline.filter(|_| pass.number > 0) {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            let seconds_left = duration_to_seconds(deadline - now);
+            let affordable = seconds_left / (seconds_per_sample * pixels.len() as f64);
+            if affordable < 1.0 {
+                break;
+            }
+            samples = (samples as f64).min(affordable) as u32;
+        }
+
+        let pass_start = Instant::now();
+        if render_pass(scene,
+                       camera,
+                       &mut film,
+                       &pixels,
+                       |_| scene.sampling_pattern.pass_offsets(pass.samples_per_pixel, samples),
+                       &mut on_rows)
+                   .is_none() {
+            break;
+        }
+        seconds_per_sample = duration_to_seconds(pass_start.elapsed()) /
+                             (pixels.len() as f64 * samples as f64);
+
+        if let Some(threshold) = scene.noise_threshold {
+            pixels.retain(|&i| !has_converged(&film, i % width, i / width, threshold));
+        }
+
+        pass = RenderedPass {
+            number: pass.number + 1,
+            samples_per_pixel: pass.samples_per_pixel + samples,
+            samples: film.samples(),
+            image: film.to_image(),
+        };
+        on_pass(&pass);
+    }
+
+    pass
+}
+
+fn has_converged(film: &Film, x: u32, y: u32, noise_threshold: f64) -> bool {
+    if film.sample_count(x, y) < MIN_NOISE_SAMPLES {
+        return false;
+    }
+
+    match film.noise(x, y) {
+        Some(noise) => noise < noise_threshold,
+        None => false,
+    }
+}
+
+fn duration_from_seconds(seconds: f64) -> Duration {
+    let seconds = seconds.max(0.0);
+    Duration::new(seconds.trunc() as u64, (seconds.fract() * 1e9) as u32)
+}
+
+fn duration_to_seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
+
+// Renders samples for the given pixels, in order, and adds them to the film. pixel_offsets gives
+// the positions of the samples inside of a pixel, from the pixel's index. Returns the first sample
+// of every pixel, or None if rendering was cancelled.
+fn render_pass<O, F>(scene: &Scene,
+                     camera: &Camera,
+                     film: &mut Film,
+                     pixels: &[u32],
+                     pixel_offsets: O,
+                     on_rows: &mut F)
+                     -> Option<Vec<Sample>>
+    where O: Fn(u32) -> Vec<(f64, f64)> + Sync,
+          F: FnMut(&Film, Range<u32>) -> bool
+{
     use rayon::prelude::*;
 
-    let channel = Arc::new(Mutex::new(channel_tx));
+    let width = film.width;
+    let height = film.height;
+    let mut first_samples = Vec::with_capacity(pixels.len());
+
+    for band in pixels.chunks((width * BAND_ROWS) as usize) {
+        let band_samples: Vec<Vec<Sample>> = band.par_iter()
+            .map(|&i| {
+                let y = i / width;
+                let x = i - y * width;
+
+                pixel_offsets(i)
+                    .iter()
+                    .map(|&(x_offset, y_offset)| {
+                             render_sample(scene,
+                                           camera,
+                                           x as f64 + x_offset,
+                                           y as f64 + y_offset,
+                                           width,
+                                           height)
+                         })
+                    .collect()
+            })
+            .collect();
+
+        for mut samples in band_samples {
+            for sample in &samples {
+                film.add_sample(sample.x, sample.y, sample.color);
+            }
+            first_samples.push(samples.swap_remove(0));
+        }
 
-    (0u32..width * height)
-        .into_par_iter()
-        // Use .all so we can try to cancel processing if channel is closed.
-        .all(|i| {
-            let y = i / width;
-            let x = i - y * width;
-            let color = render_pixel(scene, x, y, width, height);
+        // Samples spread into rows above and below their own, so report those too.
+        let first_row = band[0] / width;
+        let last_row = band[band.len() - 1] / width;
+        let reach = film.reach();
+        let changed_rows = first_row.saturating_sub(reach)..(last_row + reach + 1).min(height);
 
-            let receipt = channel
-                .lock()
-                .send(RenderedPixel {
-                          x: x,
-                          y: y,
-                          color: color,
-                      });
+        if !on_rows(film, changed_rows) {
+            return None;
+        }
+    }
 
-            receipt.is_ok()
-        });
+    Some(first_samples)
 }
 
-fn render_pixel(scene: &Scene, x: u32, y: u32, width: u32, height: u32) -> Color {
-    let ray = Ray::create_prime(x, y, scene, width, height);
-    if let Some(intersection) = scene.trace(&ray) {
-        get_color(scene, &ray, &intersection, 0)
-    } else {
-        scene.default_color
+fn needs_refinement(samples: &[Sample], i: u32, width: u32, height: u32, threshold: f32) -> bool {
+    let y = i / width;
+    let x = i - y * width;
+    let sample = &samples[i as usize];
+
+    let mut neighbours = Vec::with_capacity(4);
+    if x > 0 {
+        neighbours.push(i - 1);
+    }
+    if x + 1 < width {
+        neighbours.push(i + 1);
+    }
+    if y > 0 {
+        neighbours.push(i - width);
+    }
+    if y + 1 < height {
+        neighbours.push(i + width);
+    }
+
+    neighbours.iter().any(|&neighbour| {
+        let other = &samples[neighbour as usize];
+        other.body_id != sample.body_id || other.color.max_difference(&sample.color) > threshold
+    })
+}
+
+fn render_sample(scene: &Scene,
+                 camera: &Camera,
+                 x: f64,
+                 y: f64,
+                 width: u32,
+                 height: u32)
+                 -> Sample {
+    let ray = match Ray::create_prime(x, y, camera, width, height) {
+        Some(ray) => ray,
+        // Pixel is not covered by the camera's projection
+        None => {
+            return Sample {
+                       x,
+                       y,
+                       color: Color::black(),
+                       body_id: None,
+                   }
+        }
+    };
+
+    match scene.trace(&ray) {
+        Some(intersection) => {
+            Sample {
+                x,
+                y,
+                color: get_color(scene, &ray, &intersection, 0),
+                body_id: Some(intersection.body as *const Body as usize),
+            }
+        }
+        None => {
+            Sample {
+                x,
+                y,
+                color: scene.default_color,
+                body_id: None,
+            }
+        }
     }
 }
 
 fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32) -> Color {
     let hit_point = ray.origin + (ray.direction * intersection.distance);
-    let body = intersection.body;
-    let surface_normal = body.surface_normal(&hit_point);
-    let material = body.material();
+    let surface_normal = intersection.normal;
+    let material = intersection.material;
 
     match material.surface {
-        Surface::Diffuse => shade_diffuse(scene, body, &hit_point, &surface_normal),
+        Surface::Diffuse => shade_diffuse(scene, ray, intersection, &hit_point),
         Surface::Reflecting { reflectivity } => {
-            let diffuse_color = shade_diffuse(scene, body, &hit_point, &surface_normal);
+            let diffuse_color = shade_diffuse(scene, ray, intersection, &hit_point);
             let reflection_ray = Ray::create_reflection(surface_normal, ray.direction, hit_point);
             (diffuse_color * (1.0 - reflectivity)) +
             (cast_ray(scene, &reflection_ray, depth + 1) * reflectivity)
@@ -95,7 +455,7 @@ fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32)
             let refraction_color;
 
             let kr = fresnel(ray.direction, surface_normal, index) as f32;
-            let surface_color = material.coloration.color(&body.texture_coords(&hit_point));
+            let surface_color = material.color(&intersection.texture_coords);
 
             if kr < 1.0 {
                 let transmission_ray = Ray::create_transmission(surface_normal,
@@ -130,12 +490,18 @@ fn cast_ray(scene: &Scene, ray: &Ray, depth: u32) -> Color {
 }
 
 fn shade_diffuse(scene: &Scene,
-                 body: &Body,
-                 hit_point: &Point3,
-                 surface_normal: &Vector3)
+                 ray: &Ray,
+                 intersection: &Intersection,
+                 hit_point: &Point3)
                  -> Color {
-    let texture_coords = body.texture_coords(&hit_point);
-    let body_color = body.color(&texture_coords);
+    // Surfaces that can be hit from both sides, like triangles and rectangles, are lit on the side
+    // that the ray comes from, whichever way their normal faces.
+    let surface_normal = &if intersection.normal.dot(ray.direction) > 0.0 {
+        -intersection.normal
+    } else {
+        intersection.normal
+    };
+    let body_color = intersection.material.color(&intersection.texture_coords);
 
     let mut final_color = Color::black();
     for light in &scene.lights {
@@ -162,7 +528,7 @@ fn shade_diffuse(scene: &Scene,
 
         let light_power = (surface_normal.dot(direction_to_light) as f32).max(0.0) *
                           light_intensity;
-        let light_reflected = body.albedo() / PI;
+        let light_reflected = intersection.material.albedo / PI;
         let light_color = light.color() * light_power * light_reflected;
 
         final_color = final_color + (body_color * light_color);
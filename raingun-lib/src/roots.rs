@@ -0,0 +1,144 @@
+// Real roots of a·x² + b·x + c, in ascending order. Falls back to the linear equation when a is
+// close to zero.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a.abs() < 1e-12 {
+        return if b.abs() < 1e-12 { Vec::new() } else { vec![-c / b] };
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return Vec::new();
+    }
+
+    // Computed like this instead of (-b ± √discriminant) / 2a, which loses precision when b is
+    // much larger than a or c.
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (x0, x1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+
+    if x0 <= x1 { vec![x0, x1] } else { vec![x1, x0] }
+}
+
+// Real roots of a·x³ + b·x² + c·x + d, in ascending order.
+pub fn solve_cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    use std::f64::consts::PI;
+
+    if a.abs() < 1e-12 {
+        return solve_quadratic(b, c, d);
+    }
+
+    // Substituting x = t - b/3a gives t³ + p·t + q, which has no squared term.
+    let (b, c, d) = (b / a, c / a, d / a);
+    let shift = -b / 3.0;
+    let p = c - b * b / 3.0;
+    let q = 2.0 * b * b * b / 27.0 - b * c / 3.0 + d;
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+
+    let mut roots = if discriminant > 0.0 {
+        // One real root, from Cardano's formula.
+        let root = discriminant.sqrt();
+        vec![(-q / 2.0 + root).cbrt() + (-q / 2.0 - root).cbrt() + shift]
+    } else if p.abs() < 1e-12 {
+        vec![shift]
+    } else {
+        // Three real roots, which are easier to find with trigonometry than with complex numbers.
+        let radius = 2.0 * (-p / 3.0).sqrt();
+        let angle = (3.0 * q / (p * radius)).clamp(-1.0, 1.0).acos() / 3.0;
+        (0..3)
+            .map(|k| radius * (angle - 2.0 * PI * k as f64 / 3.0).cos() + shift)
+            .collect()
+    };
+
+    sort(&mut roots);
+    roots
+}
+
+// Real roots of a·x⁴ + b·x³ + c·x² + d·x + e, in ascending order. The roots are found with
+// Ferrari's method, and then polished with Newton's method to make up for the precision that the
+// method loses along the way.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if a.abs() < 1e-12 {
+        return solve_cubic(b, c, d, e);
+    }
+
+    // Substituting x = y - b/4a gives y⁴ + p·y² + q·y + r, which has no cubed term.
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+    let shift = -b / 4.0;
+    let p = c - 3.0 * b * b / 8.0;
+    let q = d - b * c / 2.0 + b * b * b / 8.0;
+    let r = e - b * d / 4.0 + b * b * c / 16.0 - 3.0 * b * b * b * b / 256.0;
+
+    let mut depressed_roots = Vec::new();
+    if q.abs() < 1e-12 {
+        // A quadratic equation in y².
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                depressed_roots.push(z.sqrt());
+                depressed_roots.push(-z.sqrt());
+            }
+        }
+    } else {
+        // Splits the quartic into two quadratics, using a positive root m of the resolvent cubic.
+        let m = solve_cubic(1.0, p, p * p / 4.0 - r, -q * q / 8.0)
+            .into_iter()
+            .fold(0.0, f64::max);
+        if m > 0.0 {
+            let root = (2.0 * m).sqrt();
+            depressed_roots.extend(solve_quadratic(1.0, root, p / 2.0 + m - q / (2.0 * root)));
+            depressed_roots.extend(solve_quadratic(1.0, -root, p / 2.0 + m + q / (2.0 * root)));
+        }
+    }
+
+    let polynomial = |x: f64| (((x + b) * x + c) * x + d) * x + e;
+    let derivative = |x: f64| ((4.0 * x + 3.0 * b) * x + 2.0 * c) * x + d;
+
+    let mut roots: Vec<f64> = depressed_roots
+        .into_iter()
+        .map(|root| {
+            let mut x = root + shift;
+            for _ in 0..2 {
+                let slope = derivative(x);
+                if slope.abs() > 1e-12 {
+                    x -= polynomial(x) / slope;
+                }
+            }
+            x
+        })
+        .collect();
+
+    sort(&mut roots);
+    roots
+}
+
+fn sort(roots: &mut [f64]) {
+    roots.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+}
+
+#[test]
+fn it_solves_quadratics() {
+    assert_eq!(solve_quadratic(1.0, -3.0, 2.0), vec![1.0, 2.0]);
+    assert_eq!(solve_quadratic(2.0, 0.0, 2.0), Vec::<f64>::new());
+    assert_eq!(solve_quadratic(0.0, 2.0, -4.0), vec![2.0]);
+
+    // The small root doesn't get lost next to the big one.
+    let roots = solve_quadratic(1.0, -1e9, 1.0);
+    assert!((roots[0] - 1e-9).abs() < 1e-20);
+}
+
+#[test]
+fn it_solves_cubics_and_quartics() {
+    let close = |roots: Vec<f64>, expected: &[f64]| {
+        roots.len() == expected.len() &&
+        roots.iter().zip(expected).all(|(root, expected)| (root - expected).abs() < 1e-9)
+    };
+
+    // (x - 1)(x - 2)(x - 3)
+    assert!(close(solve_cubic(1.0, -6.0, 11.0, -6.0), &[1.0, 2.0, 3.0]));
+    // (x - 2)(x² + 1)
+    assert!(close(solve_cubic(2.0, -4.0, 2.0, -4.0), &[2.0]));
+
+    // (x - 1)(x - 2)(x - 3)(x - 4)
+    assert!(close(solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0), &[1.0, 2.0, 3.0, 4.0]));
+    // (x² - 4)(x² + 1), which has no cubed or linear term.
+    assert!(close(solve_quartic(1.0, 0.0, -3.0, 0.0, -4.0), &[-2.0, 2.0]));
+    assert!(solve_quartic(1.0, 0.0, 2.0, 0.0, 1.0).is_empty());
+}
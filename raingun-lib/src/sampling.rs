@@ -0,0 +1,194 @@
+use rand;
+
+// How sample positions are distributed inside of each pixel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+pub enum SamplingPattern {
+    // Samples in the center of each cell of an evenly spaced grid.
+    #[default]
+    Regular,
+    // One sample at a random position inside each cell of the grid (stratified sampling).
+    Jittered,
+    // Samples at uniformly random positions in the whole pixel.
+    Random,
+}
+
+// Adaptive anti-aliasing: every pixel starts out with a single sample, and pixels that stand out
+// from their neighbours are refined with more samples.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct AdaptiveSampling {
+    // Pixels are refined when any color channel differs more than this from a neighbouring pixel,
+    // or when a neighbouring pixel hit a different body.
+    pub threshold: f32,
+    // Total number of samples for refined pixels.
+    pub max_samples: u32,
+}
+
+impl SamplingPattern {
+    // Returns sample positions inside of a pixel, with 0.0 being the left/top edge and 1.0 the
+    // right/bottom edge. At least one position is always returned.
+    pub fn pixel_offsets(&self, count: u32) -> Vec<(f64, f64)> {
+        let count = count.max(1);
+
+        if *self == SamplingPattern::Random {
+            return (0..count).map(|_| (rand::random(), rand::random())).collect();
+        }
+
+        let rows = grid_rows(count);
+        let cell_height = 1.0 / rows as f64;
+
+        let mut offsets = Vec::with_capacity(count as usize);
+        for row in 0..rows {
+            let columns = row_length(count, rows, row);
+            let cell_width = 1.0 / columns as f64;
+
+            for column in 0..columns {
+                let (x, y) = if *self == SamplingPattern::Jittered {
+                    (rand::random(), rand::random())
+                } else {
+                    (0.5, 0.5)
+                };
+
+                offsets.push(((column as f64 + x) * cell_width, (row as f64 + y) * cell_height));
+            }
+        }
+
+        offsets
+    }
+
+    // Sample positions for one pass of progressive rendering, continuing after the first samples
+    // that earlier passes took. Every pass has to land in new places, so both Regular and Jittered
+    // use the Halton sequence, which fills in the gaps that the earlier samples left.
+    pub fn pass_offsets(&self, first: u32, count: u32) -> Vec<(f64, f64)> {
+        if *self == SamplingPattern::Random {
+            return self.pixel_offsets(count);
+        }
+
+        // The sequence starts at (0, 0), which is skipped.
+        (first + 1..first + count.max(1) + 1)
+            .map(|index| (radical_inverse(index, 2), radical_inverse(index, 3)))
+            .collect()
+    }
+
+    // Positions for the rest of the samples of a pixel that already has a sample at the taken
+    // position. These are the positions for count samples, without the one that is closest to the
+    // taken position.
+    pub fn remaining_offsets(&self, count: u32, taken: (f64, f64)) -> Vec<(f64, f64)> {
+        let mut offsets = self.pixel_offsets(count);
+        let distance = |&(x, y): &(f64, f64)| (x - taken.0).powi(2) + (y - taken.1).powi(2);
+
+        let closest = (0..offsets.len())
+            .min_by(|&a, &b| {
+                        distance(&offsets[a])
+                            .partial_cmp(&distance(&offsets[b]))
+                            .unwrap_or(::std::cmp::Ordering::Equal)
+                    })
+            .unwrap_or(0);
+        offsets.remove(closest);
+        offsets
+    }
+}
+
+// Mirrors the digits of the index in the base around the decimal point, so 6 in base 2 (110)
+// becomes 0.011 (0.375).
+fn radical_inverse(mut index: u32, base: u32) -> f64 {
+    let mut result = 0.0;
+    let mut digit_value = 1.0 / base as f64;
+
+    while index > 0 {
+        result += (index % base) as f64 * digit_value;
+        index /= base;
+        digit_value /= base as f64;
+    }
+
+    result
+}
+
+// Number of rows in a grid of count cells that is as square as possible. Counts that aren't a
+// product of two close numbers leave some rows with one cell less than the others.
+fn grid_rows(count: u32) -> u32 {
+    let columns = (count as f64).sqrt().ceil() as u32;
+    count.div_ceil(columns)
+}
+
+// Number of cells in the row, spreading the cells over the rows as evenly as possible.
+fn row_length(count: u32, rows: u32, row: u32) -> u32 {
+    count * (row + 1) / rows - count * row / rows
+}
+
+#[test]
+fn it_places_regular_samples_in_a_grid() {
+    assert_eq!(SamplingPattern::Regular.pixel_offsets(1), vec![(0.5, 0.5)]);
+    assert_eq!(SamplingPattern::Regular.pixel_offsets(0), vec![(0.5, 0.5)]);
+    assert_eq!(SamplingPattern::Regular.pixel_offsets(4),
+               vec![(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]);
+}
+
+#[test]
+fn it_keeps_jittered_samples_inside_their_cells() {
+    for _ in 0..10 {
+        let offsets = SamplingPattern::Jittered.pixel_offsets(6);
+        assert_eq!(offsets.len(), 6);
+
+        // 3 columns and 2 rows
+        for (i, &(x, y)) in offsets.iter().enumerate() {
+            let column = (i % 3) as f64;
+            let row = (i / 3) as f64;
+            assert!(x >= column / 3.0 && x < (column + 1.0) / 3.0);
+            assert!(y >= row / 2.0 && y < (row + 1.0) / 2.0);
+        }
+    }
+}
+
+#[test]
+fn it_spreads_prime_sample_counts_in_both_directions() {
+    assert_eq!(SamplingPattern::Regular.pixel_offsets(3),
+               vec![(0.5, 0.25), (0.25, 0.75), (0.75, 0.75)]);
+
+    // 2 rows, of 2 and 3 samples.
+    let offsets = SamplingPattern::Regular.pixel_offsets(5);
+    assert_eq!(offsets.iter().filter(|&&(_, y)| y == 0.25).count(), 2);
+    assert_eq!(offsets.iter().filter(|&&(_, y)| y == 0.75).count(), 3);
+
+    for &count in &[7, 11, 13] {
+        let offsets = SamplingPattern::Jittered.pixel_offsets(count);
+        assert!(offsets.iter().any(|&(x, _)| x < 0.5));
+        assert!(offsets.iter().any(|&(x, _)| x > 0.5));
+    }
+}
+
+#[test]
+fn it_leaves_out_the_position_that_was_already_sampled() {
+    let offsets = SamplingPattern::Regular.remaining_offsets(4, (0.7, 0.3));
+    assert_eq!(offsets, vec![(0.25, 0.25), (0.25, 0.75), (0.75, 0.75)]);
+
+    let offsets = SamplingPattern::Regular.remaining_offsets(9, (0.5, 0.5));
+    assert_eq!(offsets.len(), 8);
+    assert!(!offsets.contains(&(0.5, 0.5)));
+}
+
+#[test]
+fn it_samples_new_positions_in_every_pass() {
+    assert_eq!(SamplingPattern::Regular.pass_offsets(0, 1), vec![(0.5, 1.0 / 3.0)]);
+    assert_eq!(SamplingPattern::Regular.pass_offsets(1, 1), vec![(0.25, 2.0 / 3.0)]);
+
+    // Passes of 1, 1, 2, 4 and 8 samples.
+    let mut offsets = Vec::new();
+    for &(first, count) in &[(0, 1), (1, 1), (2, 2), (4, 4), (8, 8)] {
+        let pass = SamplingPattern::Jittered.pass_offsets(first, count);
+        assert_eq!(pass.len(), count as usize);
+
+        for offset in pass {
+            assert!(!offsets.contains(&offset), "{:?} was sampled twice", offset);
+            offsets.push(offset);
+        }
+    }
+}
+
+#[test]
+fn it_returns_the_requested_number_of_samples() {
+    for count in 1..20 {
+        assert_eq!(SamplingPattern::Regular.pixel_offsets(count).len(), count as usize);
+        assert_eq!(SamplingPattern::Random.pixel_offsets(count).len(), count as usize);
+    }
+}
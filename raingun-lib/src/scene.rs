@@ -1,52 +1,343 @@
 use bodies::*;
+use camera::Camera;
 use color::Color;
+use film::Filter;
 use image::{ImageBuffer, Rgba};
 use lights::*;
 use ray::Ray;
 use rendering;
-use rendering::RenderedPixel;
+use rendering::{RenderedPass, RenderedPixel};
+use sampling::{AdaptiveSampling, SamplingPattern};
+use serde;
 
+use std::collections::BTreeMap;
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
 
-#[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields, default, rename_all = "camelCase")]
+#[derive(Debug)]
 pub struct Scene {
-    pub fov: f64,
+    // The active camera, used by render_image and streaming_render. See Scene::use_camera.
+    pub camera: Camera,
+    // Named cameras for different shots of the same scene, and the one to use by default.
+    pub cameras: BTreeMap<String, Camera>,
+    pub default_camera: Option<String>,
     pub default_color: Color,
     pub max_recursion_depth: u32,
+    // Number of rays traced for each pixel, averaged together for anti-aliasing.
+    pub samples_per_pixel: u32,
+    pub sampling_pattern: SamplingPattern,
+    // When set, samples_per_pixel is ignored in favor of adaptive anti-aliasing.
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    // Rendering stops before samples_per_pixel is reached once the estimated noise of every pixel
+    // is below noise_threshold, or when time_limit (in seconds) runs out. Either one makes the
+    // image render in passes, like progressive rendering, and adaptive_sampling is ignored.
+    // samples_per_pixel is still the most samples a pixel gets, so noise_threshold only has an
+    // effect when samples_per_pixel is raised above its default of 1.
+    pub noise_threshold: Option<f64>,
+    pub time_limit: Option<f64>,
+    // Reconstruction filter that combines samples into pixels.
+    pub filter: Filter,
     pub bodies: Vec<Body>,
+    // Named groups of bodies that can be placed in the scene many times with Instance bodies.
+    pub geometries: BTreeMap<String, Arc<Vec<Body>>>,
     pub lights: Vec<Light>,
 }
 
-impl Default for Scene {
-    fn default() -> Scene {
-        Scene {
-            fov: 90.0,
+// How a scene is described in the scene file, before its instances are linked to their
+// geometries.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default, rename_all = "camelCase")]
+struct SceneDefinition {
+    camera: Camera,
+    // Deprecated: the field of view used to be set for the whole scene, before it had a camera.
+    // It is still read from older scene files and overrides camera.fov.
+    fov: Option<f64>,
+    cameras: BTreeMap<String, Camera>,
+    default_camera: Option<String>,
+    default_color: Color,
+    max_recursion_depth: u32,
+    samples_per_pixel: u32,
+    sampling_pattern: SamplingPattern,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    noise_threshold: Option<f64>,
+    time_limit: Option<f64>,
+    filter: Filter,
+    bodies: Vec<Body>,
+    geometries: BTreeMap<String, Arc<Vec<Body>>>,
+    lights: Vec<Light>,
+}
+
+impl Default for SceneDefinition {
+    fn default() -> SceneDefinition {
+        SceneDefinition {
+            camera: Camera::default(),
+            fov: None,
+            cameras: BTreeMap::new(),
+            default_camera: None,
             default_color: Color::default(),
             max_recursion_depth: 10,
+            samples_per_pixel: 1,
+            sampling_pattern: SamplingPattern::default(),
+            adaptive_sampling: None,
+            noise_threshold: None,
+            time_limit: None,
+            filter: Filter::default(),
             lights: Vec::default(),
             bodies: Vec::default(),
+            geometries: BTreeMap::new(),
         }
     }
 }
 
+impl From<SceneDefinition> for Scene {
+    fn from(definition: SceneDefinition) -> Scene {
+        let mut camera = definition.camera;
+        if let Some(fov) = definition.fov {
+            camera.fov = fov;
+        }
+
+        Scene {
+            camera,
+            cameras: definition.cameras,
+            default_camera: definition.default_camera,
+            default_color: definition.default_color,
+            max_recursion_depth: definition.max_recursion_depth,
+            samples_per_pixel: definition.samples_per_pixel,
+            sampling_pattern: definition.sampling_pattern,
+            adaptive_sampling: definition.adaptive_sampling,
+            noise_threshold: definition.noise_threshold,
+            time_limit: definition.time_limit,
+            filter: definition.filter,
+            bodies: definition.bodies,
+            geometries: definition.geometries,
+            lights: definition.lights,
+        }
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene::from(SceneDefinition::default())
+    }
+}
+
+impl serde::Deserialize for Scene {
+    fn deserialize<D>(deserializer: D) -> Result<Scene, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let mut scene = Scene::from(SceneDefinition::deserialize(deserializer)?);
+        scene.resolve_instances().map_err(Error::custom)?;
+        Ok(scene)
+    }
+}
+
 impl Scene {
+    // Makes one of the named cameras the active camera.
+    pub fn use_camera(&mut self, name: &str) -> Result<(), String> {
+        match self.cameras.get(name) {
+            Some(camera) => {
+                self.camera = camera.clone();
+                Ok(())
+            }
+            None => Err(format!("Scene has no camera named \"{}\"", name)),
+        }
+    }
+
+    // Links the Instance bodies to the geometries that they refer to. Loading a scene does this,
+    // but it needs to be done again before rendering when bodies or geometries are changed.
+    pub fn resolve_instances(&mut self) -> Result<(), String> {
+        for (name, bodies) in &self.geometries {
+            if bodies.is_empty() {
+                return Err(format!("Geometry \"{}\" has no bodies", name));
+            }
+            if bodies.iter().any(Body::has_instances) {
+                return Err(format!("Geometry \"{}\" can't contain instances", name));
+            }
+        }
+
+        for body in &mut self.bodies {
+            body.resolve_instances(&self.geometries)?;
+        }
+
+        Ok(())
+    }
+
     pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
-        self.bodies
-            .iter()
-            .filter_map(|body| body.intersect(ray).map(|d| Intersection::new(d, body)))
-            .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
+        closest_intersection(&self.bodies, ray)
     }
 
     pub fn render_image(&self, width: u32, height: u32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-        rendering::render_image(self, width, height)
+        self.render_image_with_camera(&self.camera, width, height).image
+    }
+
+    // Renders the image, and returns it as the last pass so that the number of samples that were
+    // taken is known.
+    pub fn render_image_with_camera(&self,
+                                    camera: &Camera,
+                                    width: u32,
+                                    height: u32)
+                                    -> RenderedPass {
+        rendering::render_image(self, camera, width, height)
     }
 
     pub fn streaming_render(&self,
                             width: u32,
                             height: u32,
                             channel_tx: Sender<RenderedPixel>)
-                            -> () {
-        rendering::render_image_stream(self, width, height, channel_tx)
+                            -> RenderedPass {
+        self.streaming_render_with_camera(&self.camera, width, height, channel_tx)
+    }
+
+    pub fn streaming_render_with_camera(&self,
+                                        camera: &Camera,
+                                        width: u32,
+                                        height: u32,
+                                        channel_tx: Sender<RenderedPixel>)
+                                        -> RenderedPass {
+        rendering::render_image_stream(self, camera, width, height, channel_tx)
     }
+
+    // Renders the whole image with few samples per pixel first, and then refines it pass by pass
+    // until samples_per_pixel is reached. on_pass is called with the image after every pass, and
+    // the last pass is returned.
+    pub fn progressive_render_with_camera<F>(&self,
+                                             camera: &Camera,
+                                             width: u32,
+                                             height: u32,
+                                             on_pass: F)
+                                             -> RenderedPass
+        where F: FnMut(&RenderedPass)
+    {
+        rendering::render_image_progressive(self, camera, width, height, on_pass)
+    }
+
+    pub fn streaming_progressive_render_with_camera<F>(&self,
+                                                       camera: &Camera,
+                                                       width: u32,
+                                                       height: u32,
+                                                       channel_tx: Sender<RenderedPixel>,
+                                                       on_pass: F)
+                                                       -> RenderedPass
+        where F: FnMut(&RenderedPass)
+    {
+        rendering::render_image_stream_progressive(self, camera, width, height, channel_tx, on_pass)
+    }
+}
+
+#[test]
+fn it_reads_the_scene_fov_into_the_camera() {
+    let scene = Scene::from(SceneDefinition { fov: Some(45.0), ..SceneDefinition::default() });
+    assert_eq!(scene.camera.fov, 45.0);
+
+    let scene = Scene::from(SceneDefinition::default());
+    assert_eq!(scene.camera.fov, Camera::default().fov);
+}
+
+#[test]
+fn it_resolves_instances_to_their_materials() {
+    use material::{test_material, Coloration, Material};
+    use transform::Transform;
+    use super::{Point3, Vector3};
+    use cgmath::prelude::*;
+
+    let material = |color: Color| {
+        Material {
+            coloration: Coloration::Color(color),
+            ..test_material()
+        }
+    };
+    let instance = |geometry: &str, x: f64, material: Option<Material>| {
+        Body::Instance(Instance {
+                           geometry: String::from(geometry),
+                           material,
+                           transform: Transform::new(Vector3::new(x, 0.0, -5.0),
+                                                     Vector3::zero(),
+                                                     Vector3::new(1.0, 1.0, 1.0)),
+                           bodies: Arc::new(Vec::new()),
+                       })
+    };
+
+    let mut scene = Scene::default();
+    let sphere = Sphere {
+        center: Point3::new(0.0, 0.0, 0.0),
+        radius: 1.0,
+        material: material(Color::new(1.0, 0.0, 0.0)),
+        transform: None,
+    };
+    scene.geometries.insert(String::from("ball"), Arc::new(vec![Body::Sphere(sphere)]));
+    scene.bodies.push(instance("ball", 0.0, None));
+    scene.bodies.push(instance("ball", 3.0, Some(material(Color::new(0.0, 0.0, 1.0)))));
+    scene.resolve_instances().unwrap();
+
+    let ray = Ray::new(Point3::new(3.0, 0.0, 0.0), -Vector3::unit_z());
+    let intersection = scene.trace(&ray).unwrap();
+    assert!((intersection.distance - 4.0).abs() < 1e-9);
+    assert!(::std::ptr::eq(intersection.body, &scene.bodies[1]));
+    assert_eq!(intersection.material.color(&intersection.texture_coords),
+               Color::new(0.0, 0.0, 1.0));
+
+    let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), -Vector3::unit_z());
+    let intersection = scene.trace(&ray).unwrap();
+    assert_eq!(intersection.material.color(&intersection.texture_coords),
+               Color::new(1.0, 0.0, 0.0));
+
+    scene.bodies.push(instance("tree", 0.0, None));
+    assert_eq!(scene.resolve_instances().unwrap_err(),
+               "Scene has no geometry named \"tree\"");
+}
+
+#[test]
+fn it_resolves_instances_inside_of_csg_bodies() {
+    use csg::{Csg, CsgOperation};
+    use material::test_material;
+    use super::{Point3, Vector3};
+
+    let material = test_material();
+    let instance = Instance {
+        geometry: String::from("ball"),
+        material: None,
+        transform: None,
+        bodies: Arc::new(Vec::new()),
+    };
+    assert!(instance.material().is_none());
+
+    let sphere = |x: f64| {
+        Body::Sphere(Sphere {
+                         center: Point3::new(x, 0.0, -5.0),
+                         radius: 1.0,
+                         material: material.clone(),
+                         transform: None,
+                     })
+    };
+    let csg = Csg::new(CsgOperation::Difference,
+                       vec![sphere(0.0), Body::Instance(instance)],
+                       None)
+            .unwrap();
+
+    let mut scene = Scene::default();
+    scene.geometries.insert(String::from("ball"), Arc::new(vec![sphere(1.0)]));
+    scene.bodies.push(Body::Csg(csg));
+    scene.resolve_instances().unwrap();
+
+    // The instance takes the right half of the sphere away.
+    let ray = Ray::new(Point3::new(0.8, 0.0, 0.0), -Vector3::unit_z());
+    assert!(scene.trace(&ray).is_none());
+    let ray = Ray::new(Point3::new(-0.5, 0.0, 0.0), -Vector3::unit_z());
+    assert!(scene.trace(&ray).is_some());
+
+    // Geometries that aren't solid can't be combined.
+    scene.geometries.insert(String::from("ball"),
+                            Arc::new(vec![Body::Triangle(Triangle {
+                                                             vertices: [Point3::new(0.0, 0.0, 0.0),
+                                                                        Point3::new(1.0, 0.0, 0.0),
+                                                                        Point3::new(0.0, 1.0, 0.0)],
+                                                             normals: None,
+                                                             texture_coords: None,
+                                                             material: material.clone(),
+                                                             transform: None,
+                                                         })]));
+    assert_eq!(scene.resolve_instances().unwrap_err(),
+               "Body 2 can't be combined, only closed bodies like spheres and boxes can");
 }
@@ -0,0 +1,387 @@
+use serde;
+
+use bodies::{box_texture_coords, BoxTextureLayout, Intersectable};
+use cgmath::prelude::*;
+use marching::sphere_trace;
+use material::{Material, TextureCoords};
+use ray::Ray;
+use roots::solve_quadratic;
+use transform::Transform;
+use super::{Point3, Vector3};
+
+// How far apart the points are that the gradient of the distance is estimated from.
+const GRADIENT_STEP: f64 = 1e-6;
+
+fn default_max_steps() -> u32 {
+    256
+}
+
+fn default_epsilon() -> f64 {
+    1e-6
+}
+
+fn default_max_distance() -> f64 {
+    f64::INFINITY
+}
+
+// A shape described by its signed distance function, as a tree of primitives combined and changed
+// by operators. Renaming on the enum itself would rename the variants, so the fields are renamed
+// per variant.
+#[derive(Debug, Clone, Deserialize)]
+pub enum SdfShape {
+    Sphere { center: Point3, radius: f64 },
+    #[serde(rename_all = "camelCase")]
+    Box { center: Point3, half_extents: Vector3 },
+    // Lies flat, around an axis that points along Y.
+    #[serde(rename_all = "camelCase")]
+    Torus {
+        center: Point3,
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    Capsule {
+        start: Point3,
+        end: Point3,
+        radius: f64,
+    },
+    // Blends the shapes together, with rounded seams up to the smoothness wide where they meet.
+    SmoothUnion {
+        #[serde(deserialize_with = "deserialize_blended_shapes")]
+        shapes: Vec<SdfShape>,
+        smoothness: f64,
+    },
+    // Takes the second shape away from the first one.
+    Subtraction {
+        shape: Box<SdfShape>,
+        subtracted: Box<SdfShape>,
+    },
+    // Turns the shape around the Y axis by the angle in degrees for every unit along it.
+    Twist { shape: Box<SdfShape>, angle: f64 },
+    // Copies of the shape at every multiple of the spacing. Axes with a spacing of zero aren't
+    // repeated along. Copies should fit in their own cell, or they get cut off.
+    Repeat {
+        shape: Box<SdfShape>,
+        spacing: Vector3,
+    },
+}
+
+// A smooth union of nothing has no surface to find, so it needs at least one shape.
+fn deserialize_blended_shapes<D>(deserializer: D) -> Result<Vec<SdfShape>, D::Error>
+    where D: serde::Deserializer
+{
+    use serde::de::Error;
+    use serde::Deserialize;
+
+    let shapes = Vec::<SdfShape>::deserialize(deserializer)?;
+    if shapes.is_empty() {
+        return Err(Error::custom("Smooth unions need at least one shape"));
+    }
+    Ok(shapes)
+}
+
+impl SdfShape {
+    // Distance from the point to the closest surface, negative inside the shape. It can be less
+    // than the actual distance, but never more.
+    pub fn distance(&self, point: &Point3) -> f64 {
+        match *self {
+            SdfShape::Sphere { ref center, radius } => (point - center).magnitude() - radius,
+            SdfShape::Box { ref center, ref half_extents } => {
+                let offset = point - center;
+                let outside = Vector3::new(offset.x.abs() - half_extents.x,
+                                           offset.y.abs() - half_extents.y,
+                                           offset.z.abs() - half_extents.z);
+                let inside = outside.x.max(outside.y).max(outside.z).min(0.0);
+                Vector3::new(outside.x.max(0.0), outside.y.max(0.0), outside.z.max(0.0))
+                    .magnitude() + inside
+            }
+            SdfShape::Torus { ref center, major_radius, minor_radius } => {
+                let offset = point - center;
+                let from_ring = (offset.x * offset.x + offset.z * offset.z).sqrt() - major_radius;
+                (from_ring * from_ring + offset.y * offset.y).sqrt() - minor_radius
+            }
+            SdfShape::Capsule { ref start, ref end, radius } => {
+                let axis = end - start;
+                let offset = point - start;
+                let along = if axis.magnitude2() > 0.0 {
+                    (offset.dot(axis) / axis.magnitude2()).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                (offset - axis * along).magnitude() - radius
+            }
+            SdfShape::SmoothUnion { ref shapes, smoothness } => {
+                shapes
+                    .iter()
+                    .map(|shape| shape.distance(point))
+                    .fold(None, |blended, distance| match blended {
+                        Some(blended) => Some(smooth_min(blended, distance, smoothness)),
+                        None => Some(distance),
+                    })
+                    .unwrap_or(f64::INFINITY)
+            }
+            SdfShape::Subtraction { ref shape, ref subtracted } => {
+                shape.distance(point).max(-subtracted.distance(point))
+            }
+            SdfShape::Twist { ref shape, angle } => {
+                let rate = angle.to_radians();
+                let (sin, cos) = (rate * point.y).sin_cos();
+                let twisted = Point3::new(cos * point.x - sin * point.z,
+                                          point.y,
+                                          sin * point.x + cos * point.z);
+
+                // Twisting stretches space more the further it is from the axis, so the distance
+                // is shortened by as much to keep it from overshooting the surface.
+                let radius = (point.x * point.x + point.z * point.z).sqrt();
+                shape.distance(&twisted) / (1.0 + rate * rate * radius * radius).sqrt()
+            }
+            SdfShape::Repeat { ref shape, ref spacing } => {
+                let repeat = |position: f64, spacing: f64| if spacing > 0.0 {
+                    position - spacing * (position / spacing).round()
+                } else {
+                    position
+                };
+                shape.distance(&Point3::new(repeat(point.x, spacing.x),
+                                            repeat(point.y, spacing.y),
+                                            repeat(point.z, spacing.z)))
+            }
+        }
+    }
+
+    // The center and radius of a sphere that the whole shape fits in, or None when it goes on
+    // forever.
+    pub fn bounding_sphere(&self) -> Option<(Point3, f64)> {
+        match *self {
+            SdfShape::Sphere { center, radius } => Some((center, radius)),
+            SdfShape::Box { center, half_extents } => Some((center, half_extents.magnitude())),
+            SdfShape::Torus { center, major_radius, minor_radius } => {
+                Some((center, major_radius + minor_radius))
+            }
+            SdfShape::Capsule { start, end, radius } => {
+                Some((start.midpoint(end), (end - start).magnitude() / 2.0 + radius))
+            }
+            SdfShape::SmoothUnion { ref shapes, smoothness } => {
+                let spheres = shapes
+                    .iter()
+                    .map(SdfShape::bounding_sphere)
+                    .collect::<Option<Vec<_>>>()?;
+                if spheres.is_empty() {
+                    return None;
+                }
+
+                // Every blend can bulge out by up to a quarter of the smoothness.
+                let bulge = smoothness.max(0.0) * (spheres.len() - 1) as f64 / 4.0;
+                let center = Point3::centroid(&spheres
+                                                   .iter()
+                                                   .map(|&(center, _)| center)
+                                                   .collect::<Vec<_>>());
+                let radius = spheres
+                    .iter()
+                    .map(|&(other, radius)| (other - center).magnitude() + radius)
+                    .fold(0.0, f64::max);
+                Some((center, radius + bulge))
+            }
+            SdfShape::Subtraction { ref shape, .. } => shape.bounding_sphere(),
+            SdfShape::Twist { ref shape, .. } => {
+                // Points keep their distance to the Y axis at the height of the sphere's center as
+                // they turn around it.
+                shape.bounding_sphere().map(|(center, radius)| {
+                    let from_axis = (center.x * center.x + center.z * center.z).sqrt();
+                    (Point3::new(0.0, center.y, 0.0), radius + from_axis)
+                })
+            }
+            SdfShape::Repeat { ref shape, spacing } => {
+                if spacing == Vector3::zero() {
+                    shape.bounding_sphere()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+// The smaller of the two distances, rounded off where they are within the smoothness of each other.
+fn smooth_min(a: f64, b: f64, smoothness: f64) -> f64 {
+    if smoothness <= 0.0 {
+        return a.min(b);
+    }
+
+    let blend = (0.5 + 0.5 * (b - a) / smoothness).clamp(0.0, 1.0);
+    b + (a - b) * blend - smoothness * blend * (1.0 - blend)
+}
+
+// A body with the surface of an SDF shape, found by sphere tracing. Rays hit once they get within
+// epsilon of the surface, and give up after max_steps steps. Only the part of the ray within the
+// shape's bounding sphere is traced, so rays that miss it don't take any steps. Shapes that repeat
+// forever have no bounds, and are traced as far as max_distance.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sdf {
+    pub shape: SdfShape,
+    #[serde(default = "default_max_steps")]
+    pub max_steps: u32,
+    #[serde(default = "default_epsilon")]
+    pub epsilon: f64,
+    #[serde(default = "default_max_distance")]
+    pub max_distance: f64,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+}
+
+impl Intersectable for Sdf {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (near, far) = match self.shape.bounding_sphere() {
+            Some((center, radius)) => {
+                let offset = ray.origin - center;
+                let roots = solve_quadratic(ray.direction.magnitude2(),
+                                            2.0 * offset.dot(ray.direction),
+                                            offset.magnitude2() - radius * radius);
+                if roots.len() < 2 || roots[1] < 0.0 {
+                    return None;
+                }
+                (roots[0], roots[1])
+            }
+            None => (0.0, f64::INFINITY),
+        };
+
+        // Rays that leave the surface, like shadows and reflections, start within epsilon of it.
+        // They are moved off it first, so they don't hit it again right away.
+        let start = if self.shape.distance(&ray.origin).abs() < self.epsilon {
+            self.epsilon * 100.0
+        } else {
+            near.max(0.0)
+        };
+        let origin = ray.origin + ray.direction * start;
+
+        // Inside the shape the distances are negative, so rays from there look for the way out.
+        let sign = self.shape.distance(&origin).signum();
+        sphere_trace(|point: &Point3| sign * self.shape.distance(point),
+                     &Ray::new(origin, ray.direction),
+                     far.min(self.max_distance) - start,
+                     self.max_steps,
+                     self.epsilon)
+                .map(|distance| start + distance)
+    }
+
+    fn surface_normal(&self, hit_point: &Point3) -> Vector3 {
+        // The direction in which the distance grows the fastest.
+        let slope = |axis: Vector3| {
+            self.shape.distance(&(*hit_point + axis * GRADIENT_STEP)) -
+            self.shape.distance(&(*hit_point + axis * -GRADIENT_STEP))
+        };
+        let gradient = Vector3::new(slope(Vector3::unit_x()),
+                                    slope(Vector3::unit_y()),
+                                    slope(Vector3::unit_z()));
+
+        if gradient.magnitude2() > 0.0 {
+            gradient.normalize()
+        } else {
+            Vector3::unit_y()
+        }
+    }
+
+    // The texture is projected onto the surface along the axis that it faces the most, and repeats
+    // every unit.
+    fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
+        box_texture_coords(BoxTextureLayout::Repeated,
+                           &self.surface_normal(hit_point),
+                           &hit_point.to_vec())
+    }
+}
+
+#[test]
+fn it_intersects_sdf_bodies() {
+    use material::test_material;
+
+    let sdf = Sdf {
+        shape: SdfShape::Subtraction {
+            shape: Box::new(SdfShape::Box {
+                                center: Point3::new(0.0, 0.0, 0.0),
+                                half_extents: Vector3::new(1.0, 1.0, 1.0),
+                            }),
+            subtracted: Box::new(SdfShape::Sphere {
+                                     center: Point3::new(0.0, 0.0, 1.0),
+                                     radius: 0.5,
+                                 }),
+        },
+        max_steps: 256,
+        epsilon: 1e-9,
+        max_distance: 100.0,
+        material: test_material(),
+        transform: None,
+    };
+
+    // Into the front of the box, and into the dent that was taken out of it.
+    let ray = Ray::new(Point3::new(0.8, 0.0, 5.0), -Vector3::unit_z());
+    assert!((sdf.intersect(&ray).unwrap() - 4.0).abs() < 1e-6);
+    let normal = sdf.surface_normal(&Point3::new(0.8, 0.0, 1.0));
+    assert!((normal - Vector3::unit_z()).magnitude() < 1e-6);
+
+    let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), -Vector3::unit_z());
+    assert!((sdf.intersect(&ray).unwrap() - 4.5).abs() < 1e-6);
+    let normal = sdf.surface_normal(&Point3::new(0.0, 0.0, 0.5));
+    assert!((normal - Vector3::unit_z()).magnitude() < 1e-6);
+
+    // Out of the box from inside, and away from the surface it starts on.
+    let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::unit_x());
+    assert!((sdf.intersect(&ray).unwrap() - 1.0).abs() < 1e-6);
+    let ray = Ray::new(Point3::new(1.0, 0.0, 0.0), Vector3::unit_x());
+    assert_eq!(sdf.intersect(&ray), None);
+
+    // Shapes melt into each other, and copies of them fill space.
+    let blended = SdfShape::SmoothUnion {
+        shapes: vec![SdfShape::Sphere {
+                         center: Point3::new(-1.0, 0.0, 0.0),
+                         radius: 1.0,
+                     },
+                     SdfShape::Sphere {
+                         center: Point3::new(1.0, 0.0, 0.0),
+                         radius: 1.0,
+                     }],
+        smoothness: 1.0,
+    };
+    assert!(blended.distance(&Point3::new(0.0, 0.5, 0.0)) < 0.0);
+    let repeated = SdfShape::Repeat {
+        shape: Box::new(blended),
+        spacing: Vector3::new(0.0, 0.0, 10.0),
+    };
+    assert!(repeated.distance(&Point3::new(-1.0, 0.0, 30.0)) < 0.0);
+    assert!(repeated.distance(&Point3::new(-1.0, 0.0, 35.0)) > 0.0);
+}
+
+#[test]
+fn it_only_traces_sdf_bodies_within_their_bounds() {
+    use material::test_material;
+
+    let twisted = SdfShape::Twist {
+        shape: Box::new(SdfShape::Sphere {
+                            center: Point3::new(3.0, 1.0, 4.0),
+                            radius: 1.0,
+                        }),
+        angle: 30.0,
+    };
+    assert_eq!(twisted.bounding_sphere(), Some((Point3::new(0.0, 1.0, 0.0), 6.0)));
+    let repeated = SdfShape::Repeat {
+        shape: Box::new(twisted),
+        spacing: Vector3::new(1.0, 0.0, 0.0),
+    };
+    assert_eq!(repeated.bounding_sphere(), None);
+
+    // Far away, with only a few steps to find it in.
+    let sdf = Sdf {
+        shape: SdfShape::Sphere {
+            center: Point3::new(0.0, 0.0, -5000.0),
+            radius: 1.0,
+        },
+        max_steps: 8,
+        epsilon: 1e-9,
+        max_distance: default_max_distance(),
+        material: test_material(),
+        transform: None,
+    };
+    let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), -Vector3::unit_z());
+    assert!((sdf.intersect(&ray).unwrap() - 4999.0).abs() < 1e-6);
+    let ray = Ray::new(Point3::new(0.0, 2.0, 0.0), -Vector3::unit_z());
+    assert_eq!(sdf.intersect(&ray), None);
+}
@@ -0,0 +1,116 @@
+use image::{ImageBuffer, Rgba};
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct Stereo {
+    // Distance between the two eyes, in world units.
+    pub interocular_distance: f64,
+    // Distance from the camera where both eyes converge. Things at this distance appear to be at
+    // the same depth as the screen.
+    pub convergence_distance: f64,
+    #[serde(default)]
+    pub layout: StereoLayout,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+pub enum StereoLayout {
+    // One image per eye, written next to the requested output file.
+    SeparateFiles,
+    // Left eye on the left half and right eye on the right half of a double-width image.
+    #[default]
+    SideBySide,
+    // Left eye on the top half and right eye on the bottom half of a double-height image.
+    OverUnder,
+    // Red/cyan anaglyph; red channel from the left eye, green and blue from the right eye.
+    Anaglyph,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Eye::Left => "left",
+            Eye::Right => "right",
+        }
+    }
+}
+
+impl StereoLayout {
+    // Size of the composite image, given the size of each eye's image. Separate files are
+    // composited side by side and split up again when they are saved.
+    pub fn composite_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match *self {
+            StereoLayout::SeparateFiles |
+            StereoLayout::SideBySide => (width * 2, height),
+            StereoLayout::OverUnder => (width, height * 2),
+            StereoLayout::Anaglyph => (width, height),
+        }
+    }
+
+    // Places a pixel rendered for one of the eyes into the composite image.
+    pub fn put_pixel(&self,
+                     image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+                     eye: Eye,
+                     x: u32,
+                     y: u32,
+                     color: Rgba<u8>) {
+        let half_width = image.width() / 2;
+        let half_height = image.height() / 2;
+
+        match (*self, eye) {
+            (StereoLayout::SeparateFiles, Eye::Left) |
+            (StereoLayout::SideBySide, Eye::Left) |
+            (StereoLayout::OverUnder, Eye::Left) => image.put_pixel(x, y, color),
+            (StereoLayout::SeparateFiles, Eye::Right) |
+            (StereoLayout::SideBySide, Eye::Right) => image.put_pixel(x + half_width, y, color),
+            (StereoLayout::OverUnder, Eye::Right) => image.put_pixel(x, y + half_height, color),
+            (StereoLayout::Anaglyph, Eye::Left) => {
+                let pixel = image.get_pixel_mut(x, y);
+                pixel.data[0] = color.data[0];
+                pixel.data[3] = 255;
+            }
+            (StereoLayout::Anaglyph, Eye::Right) => {
+                let pixel = image.get_pixel_mut(x, y);
+                pixel.data[1] = color.data[1];
+                pixel.data[2] = color.data[2];
+                pixel.data[3] = 255;
+            }
+        }
+    }
+
+    pub fn compose(&self,
+                   left: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+                   right: &ImageBuffer<Rgba<u8>, Vec<u8>>)
+                   -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let (width, height) = self.composite_size(left.width(), left.height());
+        let mut composite = ImageBuffer::new(width, height);
+
+        for (eye, image) in [(Eye::Left, left), (Eye::Right, right)] {
+            for (x, y, pixel) in image.enumerate_pixels() {
+                self.put_pixel(&mut composite, eye, x, y, *pixel);
+            }
+        }
+
+        composite
+    }
+}
+
+#[test]
+fn it_composes_anaglyphs_from_both_eyes() {
+    let left = ImageBuffer::from_pixel(2, 1, Rgba { data: [10, 20, 30, 255] });
+    let right = ImageBuffer::from_pixel(2, 1, Rgba { data: [40, 50, 60, 255] });
+
+    let composite = StereoLayout::Anaglyph.compose(&left, &right);
+    assert_eq!(composite.dimensions(), (2, 1));
+    assert_eq!(composite.get_pixel(1, 0).data, [10, 50, 60, 255]);
+
+    let composite = StereoLayout::OverUnder.compose(&left, &right);
+    assert_eq!(composite.dimensions(), (2, 2));
+    assert_eq!(composite.get_pixel(0, 0).data, [10, 20, 30, 255]);
+    assert_eq!(composite.get_pixel(0, 1).data, [40, 50, 60, 255]);
+}
@@ -0,0 +1,239 @@
+use serde;
+use std::f64::consts::PI;
+
+use bodies::{Intersectable, Solid};
+use cgmath::prelude::*;
+use cylinders::angle_around;
+use material::{Material, TextureCoords};
+use ray::Ray;
+use roots::{solve_quadratic, solve_quartic};
+use transform::Transform;
+use super::{Point3, Vector3};
+
+// Rays that meet the surface at less of a slope than this only touch it, without going in or out.
+const TANGENT_SLOPE: f64 = 1e-6;
+
+// A ring made by sweeping a circle with the minor radius around the axis, at the major radius
+// from the center.
+#[derive(Debug, Clone)]
+pub struct Torus {
+    pub center: Point3,
+    // Always normalized; see unit_axis.
+    pub axis: Vector3,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Material,
+    pub transform: Option<Transform>,
+}
+
+// How a torus is described in the scene file. The axis can have any length but zero.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TorusDefinition {
+    center: Point3,
+    axis: Vector3,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Material,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+// The axis of a torus without its length, which it needs to have a direction.
+fn unit_axis(axis: Vector3) -> Result<Vector3, String> {
+    if axis.magnitude2() > 0.0 {
+        Ok(axis.normalize())
+    } else {
+        Err(String::from("The axis of a torus can't be zero"))
+    }
+}
+
+impl Torus {
+    // Distances along the ray to where it passes through the surface, in order.
+    fn distances(&self, ray: &Ray) -> Vec<f64> {
+        let major_squared = self.major_radius * self.major_radius;
+        let minor_squared = self.minor_radius * self.minor_radius;
+
+        // Rays that start far away make for large coefficients that lose a lot of precision, so
+        // the equation is solved from where the ray enters the bounding sphere instead.
+        let outer_radius = self.major_radius + self.minor_radius;
+        let to_origin = ray.origin - self.center;
+        let start = match solve_quadratic(1.0,
+                                          2.0 * to_origin.dot(ray.direction),
+                                          to_origin.magnitude2() -
+                                          outer_radius * outer_radius)
+                      .first() {
+            Some(&distance) => distance,
+            None => return Vec::new(),
+        };
+        let origin = to_origin + ray.direction * start;
+
+        // Points on the surface satisfy (|p|² + R² - r²)² = 4R²(|p|² - (p·axis)²). Putting in the
+        // point at each distance along the ray gives a quartic equation.
+        let k = origin.magnitude2() + major_squared - minor_squared;
+        let e = origin.dot(ray.direction);
+        let origin_along = origin.dot(self.axis);
+        let direction_along = ray.direction.dot(self.axis);
+
+        solve_quartic(1.0,
+                      4.0 * e,
+                      4.0 * e * e + 2.0 * k -
+                      4.0 * major_squared * (1.0 - direction_along * direction_along),
+                      4.0 * e * k - 8.0 * major_squared * (e - origin_along * direction_along),
+                      k * k -
+                      4.0 * major_squared * (origin.magnitude2() - origin_along * origin_along))
+                .into_iter()
+                .map(|distance| distance + start)
+                .collect()
+    }
+
+    // Splits the position of a point into how far it is along the axis, and its offset from the
+    // axis in the plane of the ring.
+    fn split(&self, point: &Point3) -> (f64, Vector3) {
+        let vector = point - self.center;
+        let along = vector.dot(self.axis);
+        (along, vector - self.axis * along)
+    }
+}
+
+impl Intersectable for Torus {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.distances(ray)
+            .into_iter()
+            .find(|&distance| distance >= 0.0)
+    }
+
+    fn surface_normal(&self, hit_point: &Point3) -> Vector3 {
+        // Points away from the closest point on the circle in the middle of the tube.
+        // Points on the axis are as close to all of the circle, so it points away from its center.
+        let (_, offset) = self.split(hit_point);
+        let ring_point = if offset.magnitude2() > 0.0 {
+            self.center + offset.normalize() * self.major_radius
+        } else {
+            self.center
+        };
+        (hit_point - ring_point).normalize()
+    }
+
+    // X goes around the axis, and Y around the tube starting on its inside.
+    fn texture_coords(&self, hit_point: &Point3) -> TextureCoords {
+        let (along, offset) = self.split(hit_point);
+        let tube_angle = along.atan2(offset.magnitude() - self.major_radius);
+
+        TextureCoords {
+            x: angle_around(self.axis, offset) as f32,
+            y: ((1.0 + tube_angle / PI) * 0.5) as f32,
+        }
+    }
+}
+
+impl Solid for Torus {
+    fn span_distances(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        // A ray can pass through both sides of the ring. Where it touches the surface, the roots
+        // don't come in pairs of entries and exits, so the normal decides which of them each is.
+        let mut spans = Vec::new();
+        let mut entry = None;
+        for distance in self.distances(ray) {
+            let normal = self.surface_normal(&(ray.origin + ray.direction * distance));
+            let slope = normal.dot(ray.direction);
+            if slope < -TANGENT_SLOPE && entry.is_none() {
+                entry = Some(distance);
+            } else if slope > TANGENT_SLOPE {
+                if let Some(entry) = entry.take() {
+                    spans.push((entry, distance));
+                }
+            }
+        }
+        spans
+    }
+}
+
+// The tube needs a width. Without a major radius the torus is a sphere, but it can't be negative.
+fn check_radii(major_radius: f64, minor_radius: f64) -> Result<(), String> {
+    if minor_radius <= 0.0 {
+        Err(String::from("The minor radius of a torus must be above zero"))
+    } else if major_radius < 0.0 {
+        Err(String::from("The major radius of a torus can't be negative"))
+    } else {
+        Ok(())
+    }
+}
+
+impl serde::Deserialize for Torus {
+    fn deserialize<D>(deserializer: D) -> Result<Torus, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let TorusDefinition { center, axis, major_radius, minor_radius, material, transform } =
+            TorusDefinition::deserialize(deserializer)?;
+        check_radii(major_radius, minor_radius).map_err(Error::custom)?;
+
+        Ok(Torus {
+               center,
+               axis: unit_axis(axis).map_err(Error::custom)?,
+               major_radius,
+               minor_radius,
+               material,
+               transform,
+           })
+    }
+}
+
+#[test]
+fn it_intersects_tori() {
+    use material::test_material;
+
+    let torus = Torus {
+        center: Point3::new(0.0, 0.0, 0.0),
+        axis: Vector3::unit_y(),
+        major_radius: 2.0,
+        minor_radius: 0.5,
+        material: test_material(),
+        transform: None,
+    };
+
+    // Through both sides of the ring, from far away.
+    let ray = Ray::new(Point3::new(0.0, 0.0, 1000.0), -Vector3::unit_z());
+    let spans = torus.span_distances(&ray);
+    assert_eq!(spans.len(), 2);
+    for (&(entry, exit), &(expected_entry, expected_exit)) in
+        spans.iter().zip(&[(997.5, 998.5), (1001.5, 1002.5)]) {
+        assert!((entry - expected_entry).abs() < 1e-9);
+        assert!((exit - expected_exit).abs() < 1e-9);
+    }
+
+    let normal = torus.surface_normal(&Point3::new(0.0, 0.0, 2.5));
+    assert!((normal - Vector3::unit_z()).magnitude() < 1e-9);
+
+    // Down through the hole in the middle, and into the top of the ring.
+    let ray = Ray::new(Point3::new(0.0, 5.0, 0.0), -Vector3::unit_y());
+    assert_eq!(torus.intersect(&ray), None);
+    let ray = Ray::new(Point3::new(2.0, 5.0, 0.0), -Vector3::unit_y());
+    assert!((torus.intersect(&ray).unwrap() - 4.5).abs() < 1e-9);
+    let normal = torus.surface_normal(&Point3::new(2.0, 0.5, 0.0));
+    assert!((normal - Vector3::unit_y()).magnitude() < 1e-9);
+    assert_eq!(torus.texture_coords(&Point3::new(2.0, 0.5, 0.0)).y, 0.75);
+
+    // Along the inside of the tube, touching the edge of the hole halfway.
+    let ray = Ray::new(Point3::new(-5.0, 0.0, 1.5), Vector3::unit_x());
+    let spans = torus.span_distances(&ray);
+    assert_eq!(spans.len(), 1);
+    assert!((spans[0].0 - 3.0).abs() < 1e-6);
+    assert!((spans[0].1 - 7.0).abs() < 1e-6);
+}
+
+#[test]
+fn it_normalizes_torus_axes() {
+    assert!(unit_axis(Vector3::zero()).is_err());
+    assert_eq!(unit_axis(Vector3::new(0.0, 3.0, 0.0)), Ok(Vector3::unit_y()));
+}
+
+#[test]
+fn it_rejects_tori_without_a_tube() {
+    assert!(check_radii(2.0, 0.0).is_err());
+    assert!(check_radii(2.0, -0.5).is_err());
+    assert!(check_radii(-2.0, 0.5).is_err());
+    assert!(check_radii(0.0, 0.5).is_ok());
+    assert!(check_radii(2.0, 0.5).is_ok());
+}
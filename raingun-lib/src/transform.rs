@@ -0,0 +1,251 @@
+use serde;
+
+use cgmath::{Deg, Matrix4, SquareMatrix};
+use cgmath::Transform as MatrixTransform;
+use cgmath::prelude::*;
+use ray::Ray;
+use super::{Point3, Vector3};
+
+// Places something in the scene. The inverse is kept around to move rays into object space, and
+// its transpose to move normals out of it.
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    matrix: Matrix4<f64>,
+    inverse: Matrix4<f64>,
+    normal_matrix: Matrix4<f64>,
+}
+
+// How a transform is described in the scene file. It is scaled first, then rotated around the X,
+// Y and Z axes (in that order), then rotated around the axis and then translated. The matrix is
+// applied after all of that, for transforms that can't be described otherwise.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, default, rename_all = "camelCase")]
+struct TransformDefinition {
+    translation: Vector3,
+    // Degrees around each axis.
+    rotation: Vector3,
+    axis_angle: Option<AxisAngle>,
+    scale: Vector3,
+    // Rows of an affine matrix.
+    matrix: Option<[[f64; 4]; 4]>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AxisAngle {
+    axis: Vector3,
+    // Degrees, counter-clockwise when looking at the axis' tip.
+    angle: f64,
+}
+
+impl Default for TransformDefinition {
+    fn default() -> TransformDefinition {
+        TransformDefinition {
+            translation: Vector3::zero(),
+            rotation: Vector3::zero(),
+            axis_angle: None,
+            scale: Vector3::new(1.0, 1.0, 1.0),
+            matrix: None,
+        }
+    }
+}
+
+impl TransformDefinition {
+    fn matrix(&self) -> Result<Matrix4<f64>, String> {
+        let axis_angle = match self.axis_angle {
+            Some(AxisAngle { axis, .. }) if axis.magnitude2() == 0.0 => {
+                return Err(String::from("The axis of an axis-angle rotation can't be zero"));
+            }
+            Some(AxisAngle { axis, angle }) => {
+                Matrix4::from_axis_angle(axis.normalize(), Deg(angle))
+            }
+            None => Matrix4::identity(),
+        };
+
+        let matrix = match self.matrix {
+            Some(rows) => Matrix4::from(rows).transpose(),
+            None => Matrix4::identity(),
+        };
+
+        Ok(matrix * Matrix4::from_translation(self.translation) * axis_angle *
+           Matrix4::from_angle_z(Deg(self.rotation.z)) *
+           Matrix4::from_angle_y(Deg(self.rotation.y)) *
+           Matrix4::from_angle_x(Deg(self.rotation.x)) *
+           Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z))
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            matrix: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+            normal_matrix: Matrix4::identity(),
+        }
+    }
+
+    // Returns None for matrices that can't be inverted, like when scaling by zero.
+    pub fn from_matrix(matrix: Matrix4<f64>) -> Option<Transform> {
+        matrix
+            .invert()
+            .map(|inverse| {
+                     Transform {
+                         matrix,
+                         inverse,
+                         normal_matrix: inverse.transpose(),
+                     }
+                 })
+    }
+
+    pub fn new(translation: Vector3, rotation: Vector3, scale: Vector3) -> Option<Transform> {
+        let definition = TransformDefinition {
+            translation,
+            rotation,
+            scale,
+            ..TransformDefinition::default()
+        };
+        definition.matrix().ok().and_then(Transform::from_matrix)
+    }
+
+    // Places things with the inner transform first, and then with this one.
+    pub fn compose(&self, inner: &Transform) -> Transform {
+        let inverse = inner.inverse * self.inverse;
+        Transform {
+            matrix: self.matrix * inner.matrix,
+            inverse,
+            normal_matrix: inverse.transpose(),
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4<f64> {
+        self.matrix
+    }
+
+    // Normals can't be transformed like other vectors when scaling isn't uniform. They need the
+    // inverse transpose of the matrix instead.
+    pub fn normal_matrix(&self) -> Matrix4<f64> {
+        self.normal_matrix
+    }
+
+    pub fn transform_point(&self, point: Point3) -> Point3 {
+        self.matrix.transform_point(point)
+    }
+
+    pub fn inverse_transform_point(&self, point: Point3) -> Point3 {
+        self.inverse.transform_point(point)
+    }
+
+    pub fn transform_normal(&self, normal: Vector3) -> Vector3 {
+        self.normal_matrix
+            .transform_vector(normal)
+            .normalize()
+    }
+
+    // Moves the ray into object space, where its direction is normalized again. Distances along
+    // the object space ray are scaled by the returned factor compared to the original ray; divide
+    // them by it to get distances along the original ray.
+    pub fn inverse_transform_ray(&self, ray: &Ray) -> (Ray, f64) {
+        let direction = self.inverse.transform_vector(ray.direction);
+        let scale = direction.magnitude();
+
+        (Ray::new(self.inverse.transform_point(ray.origin), direction / scale), scale)
+    }
+}
+
+impl serde::Deserialize for Transform {
+    fn deserialize<D>(deserializer: D) -> Result<Transform, D::Error>
+        where D: serde::Deserializer
+    {
+        use serde::de::Error;
+
+        let definition = TransformDefinition::deserialize(deserializer)?;
+        let matrix = definition.matrix().map_err(Error::custom)?;
+        Transform::from_matrix(matrix)
+            .ok_or_else(|| Error::custom("Transform can't be inverted, is a scale zero?"))
+    }
+}
+
+#[test]
+fn it_composes_transforms() {
+    let inner = Transform::new(Vector3::new(1.0, 0.0, 0.0),
+                               Vector3::new(0.0, 90.0, 0.0),
+                               Vector3::new(1.0, 1.0, 1.0))
+            .unwrap();
+    let outer = Transform::new(Vector3::new(0.0, 0.0, -5.0),
+                               Vector3::zero(),
+                               Vector3::new(2.0, 2.0, 2.0))
+            .unwrap();
+    let transform = outer.compose(&inner);
+
+    let point = Point3::new(0.0, 0.0, 1.0);
+    let placed = transform.transform_point(point);
+    assert!((placed - outer.transform_point(inner.transform_point(point))).magnitude() < 1e-9);
+    assert!((transform.inverse_transform_point(placed) - point).magnitude() < 1e-9);
+}
+
+#[test]
+fn it_scales_rotates_and_then_translates() {
+    let transform = Transform::new(Vector3::new(0.0, 0.0, -5.0),
+                                   Vector3::new(0.0, 90.0, 0.0),
+                                   Vector3::new(2.0, 1.0, 1.0))
+            .unwrap();
+
+    let point = transform.transform_point(Point3::new(1.0, 1.0, 0.0));
+    assert!((point - Point3::new(0.0, 1.0, -7.0)).magnitude() < 1e-9);
+
+    let normal = transform.transform_normal(Vector3::unit_x());
+    assert!((normal - -Vector3::unit_z()).magnitude() < 1e-9);
+}
+
+#[test]
+fn it_combines_axis_angle_rotations_and_matrices() {
+    let definition = TransformDefinition {
+        axis_angle: Some(AxisAngle {
+                             axis: Vector3::new(0.0, 0.0, 2.0),
+                             angle: 90.0,
+                         }),
+        matrix: Some([[1.0, 0.0, 0.0, 3.0],
+                      [0.0, 1.0, 0.0, 0.0],
+                      [0.0, 0.0, 1.0, 0.0],
+                      [0.0, 0.0, 0.0, 1.0]]),
+        ..TransformDefinition::default()
+    };
+    let transform = Transform::from_matrix(definition.matrix().unwrap()).unwrap();
+
+    let point = transform.transform_point(Point3::new(1.0, 0.0, 0.0));
+    assert!((point - Point3::new(3.0, 1.0, 0.0)).magnitude() < 1e-9);
+}
+
+#[test]
+fn it_rejects_axis_angle_rotations_around_a_zero_axis() {
+    let definition = TransformDefinition {
+        axis_angle: Some(AxisAngle {
+                             axis: Vector3::zero(),
+                             angle: 90.0,
+                         }),
+        ..TransformDefinition::default()
+    };
+    assert!(definition.matrix().is_err());
+}
+
+#[test]
+fn it_moves_rays_into_object_space() {
+    let transform = Transform::new(Vector3::new(0.0, 0.0, -5.0),
+                                   Vector3::zero(),
+                                   Vector3::new(2.0, 2.0, 2.0))
+            .unwrap();
+    let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), -Vector3::unit_z());
+
+    let (object_ray, scale) = transform.inverse_transform_ray(&ray);
+    assert!((object_ray.origin - Point3::new(0.0, 0.0, 2.5)).magnitude() < 1e-9);
+    assert!((object_ray.direction - -Vector3::unit_z()).magnitude() < 1e-9);
+    assert_eq!(scale, 0.5);
+
+    assert!(Transform::new(Vector3::zero(), Vector3::zero(), Vector3::zero()).is_none());
+}
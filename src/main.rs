@@ -1,8 +1,10 @@
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 extern crate image;
 extern crate parking_lot;
 extern crate piston_window;
+extern crate png;
 extern crate time;
 
 #[macro_use]
@@ -40,18 +42,61 @@ fn construct_app<'a, 'b>() -> clap::App<'a, 'b> {
              .help("Renders in 1080 (HD) resolution. Explicit width/height overrides.")
              .overrides_with("4k")
         )
+        .arg(Arg::with_name("samples")
+                 .short("s")
+                 .long("samples")
+                 .value_name("COUNT")
+                 .help("Number of samples per pixel. Overrides the scene's samplesPerPixel."))
         .arg(Arg::with_name("preview")
              .long("preview")
              .help("Shows render progress in a window. Window closes after rendering has finished.")
         )
+        .arg(Arg::with_name("progressive")
+             .long("progressive")
+             .help("Renders the whole image with few samples first, then refines it in passes.")
+             .long_help(
+                 "Renders the whole image with a single sample per pixel first, then refines it \
+                 pass by pass until the number of samples per pixel is reached. Each pass doubles \
+                 the number of samples."
+             )
+        )
+        .arg(Arg::with_name("write-passes")
+             .long("write-passes")
+             .help("Writes the output image after every progressive pass.")
+             .requires("progressive")
+        )
+        .arg(Arg::with_name("time-limit")
+                 .long("time-limit")
+                 .value_name("DURATION")
+                 .help("Stops adding samples when the time runs out, e.g. 30s, 2m or 500ms.")
+                 .long_help(
+                     "Stops adding samples when the time runs out, even if the number of samples \
+                     per pixel has not been reached. Renders in passes like --progressive. The \
+                     duration is a number followed by ms, s, m or h, and defaults to seconds."
+                 ))
         .arg(Arg::with_name("draft")
              .long("draft")
              .help("Renders in 800x600 and lower quality settings.")
              .overrides_with("4k")
+             .overrides_with("samples")
              .overrides_with("hd")
              .overrides_with("width")
              .overrides_with("height")
         )
+        .arg(Arg::with_name("camera")
+                 .long("camera")
+                 .value_name("NAME")
+                 .help("Renders using the named camera from the scene.")
+                 .long_help(
+                     "Renders using the named camera from the scene's \"cameras\" section. \
+                     Defaults to the scene's \"defaultCamera\", or its \"camera\" when that \
+                     is not set."
+                 ))
+        .arg(Arg::with_name("all-cameras")
+             .long("all-cameras")
+             .help("Renders every named camera in the scene to <output>-<name>.png.")
+             .conflicts_with("camera")
+        )
         .arg(Arg::with_name("output")
                  .short("o")
                  .long("output")
@@ -73,6 +118,7 @@ impl<'a, 'b> From<&'b clap::ArgMatches<'a>> for RenderOptions {
 
         if matches.is_present("draft") {
             options.max_recursion_depth = Some(4);
+            options.samples = Some(1);
         } else if matches.is_present("hd") {
             options.width = 1920;
             options.height = 1080;
@@ -91,10 +137,50 @@ impl<'a, 'b> From<&'b clap::ArgMatches<'a>> for RenderOptions {
             options.height = height;
         }
 
+        if let Some(samples) = matches.value_of("samples") {
+            let samples = samples.parse().expect("Could not parse samples");
+            options.samples = Some(samples);
+        }
+
+        if let Some(time_limit) = matches.value_of("time-limit") {
+            let time_limit = parse_duration(time_limit).expect("Could not parse time limit");
+            options.time_limit = Some(time_limit);
+        }
+
+        options.progressive = matches.is_present("progressive");
+        options.write_passes = matches.is_present("write-passes");
+
+        options.camera = matches.value_of("camera").map(String::from);
+        options.all_cameras = matches.is_present("all-cameras");
+
         options
     }
 }
 
+// Parses durations like "30s", "2m" or "500ms" into seconds.
+fn parse_duration(duration: &str) -> Option<f64> {
+    let duration = duration.trim();
+    let unit_start = duration
+        .find(|c: char| c.is_alphabetic())
+        .unwrap_or(duration.len());
+    let (number, unit) = duration.split_at(unit_start);
+
+    let number: f64 = match number.trim().parse() {
+        Ok(number) => number,
+        Err(_) => return None,
+    };
+
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 60.0 * 60.0,
+        _ => return None,
+    };
+
+    if seconds >= 0.0 { Some(seconds) } else { None }
+}
+
 fn main() {
     let matches = construct_app().get_matches();
     let render_options = RenderOptions::from(&matches);
@@ -114,20 +200,72 @@ fn main() {
     };
 
     let file = File::open(input_path).expect("Could not open input file");
-    let scene = {
+    let mut scene = {
         let mut scene: Scene = serde_yaml::from_reader(&file).expect("Could not load YAML");
         if let Some(limit) = render_options.max_recursion_depth {
             if limit < scene.max_recursion_depth {
                 scene.max_recursion_depth = limit;
             }
         }
+        if let Some(samples) = render_options.samples {
+            scene.samples_per_pixel = samples;
+        }
+        if let Some(time_limit) = render_options.time_limit {
+            scene.time_limit = Some(time_limit);
+        }
         scene
     };
 
-    if matches.is_present("preview") {
-        render_image_with_preview(&scene, &render_options, &input_path, &output_path);
+    let preview = matches.is_present("preview");
+
+    // The time limit is for everything that is rendered, not for each camera or eye.
+    let deadline = scene.time_limit.map(deadline_after);
+
+    if render_options.all_cameras {
+        let camera_names: Vec<String> = scene.cameras.keys().cloned().collect();
+        if camera_names.is_empty() {
+            println!("{} has no named cameras", input_path.to_string_lossy());
+            ::std::process::exit(2);
+        }
+
+        let camera_count = camera_names.len();
+        for (index, name) in camera_names.into_iter().enumerate() {
+            scene.use_camera(&name).unwrap();
+            render(&mut scene,
+                   &render_options,
+                   next_deadline(deadline, camera_count - index),
+                   input_path,
+                   &suffixed_path(&output_path, &name),
+                   preview);
+        }
+    } else {
+        let camera_name = render_options.camera.clone().or_else(|| scene.default_camera.clone());
+        if let Some(name) = camera_name {
+            if let Err(error) = scene.use_camera(&name) {
+                println!("{}", error);
+                ::std::process::exit(2);
+            }
+        }
+
+        render(&mut scene,
+               &render_options,
+               deadline,
+               input_path,
+               &output_path,
+               preview);
+    }
+}
+
+fn render(scene: &mut Scene,
+          render_options: &RenderOptions,
+          deadline: Option<Instant>,
+          input_path: &Path,
+          output_path: &Path,
+          preview: bool) {
+    if preview {
+        render_image_with_preview(scene, render_options, deadline, input_path, output_path);
     } else {
-        render_image_without_preview(&scene, &render_options, &input_path, &output_path);
+        render_image_without_preview(scene, render_options, deadline, input_path, output_path);
     }
 }
 
@@ -175,5 +313,69 @@ mod test {
         assert_eq!(render_options.width, 800);
         assert_eq!(render_options.height, 600);
         assert_eq!(render_options.max_recursion_depth, Some(4));
+        assert_eq!(render_options.samples, Some(1));
+    }
+
+    #[test]
+    fn it_parses_samples_argument() {
+        let matches = parse_arguments(&["x", "file"]);
+        let render_options = RenderOptions::from(&matches);
+        assert_eq!(render_options.samples, None);
+
+        let matches = parse_arguments(&["x", "--samples", "16", "file"]);
+        let render_options = RenderOptions::from(&matches);
+        assert_eq!(render_options.samples, Some(16));
+    }
+
+    #[test]
+    fn it_parses_progressive_arguments() {
+        let matches = parse_arguments(&["x", "file"]);
+        let render_options = RenderOptions::from(&matches);
+        assert_eq!(render_options.progressive, false);
+        assert_eq!(render_options.write_passes, false);
+
+        let matches = parse_arguments(&["x", "--progressive", "--write-passes", "file"]);
+        let render_options = RenderOptions::from(&matches);
+        assert_eq!(render_options.progressive, true);
+        assert_eq!(render_options.write_passes, true);
+    }
+
+    #[test]
+    fn it_parses_time_limit_argument() {
+        let matches = parse_arguments(&["x", "file"]);
+        let render_options = RenderOptions::from(&matches);
+        assert_eq!(render_options.time_limit, None);
+
+        let matches = parse_arguments(&["x", "--time-limit", "30s", "file"]);
+        let render_options = RenderOptions::from(&matches);
+        assert_eq!(render_options.time_limit, Some(30.0));
+    }
+
+    #[test]
+    fn it_parses_durations() {
+        assert_eq!(parse_duration("30s"), Some(30.0));
+        assert_eq!(parse_duration("45"), Some(45.0));
+        assert_eq!(parse_duration("500ms"), Some(0.5));
+        assert_eq!(parse_duration("2m"), Some(120.0));
+        assert_eq!(parse_duration("1.5h"), Some(5400.0));
+        assert_eq!(parse_duration("10 parsecs"), None);
+        assert_eq!(parse_duration("-1s"), None);
+        assert_eq!(parse_duration("s"), None);
+    }
+
+    #[test]
+    fn it_parses_camera_arguments() {
+        let matches = parse_arguments(&["x", "file"]);
+        let render_options = RenderOptions::from(&matches);
+        assert_eq!(render_options.camera, None);
+        assert_eq!(render_options.all_cameras, false);
+
+        let matches = parse_arguments(&["x", "--camera", "top", "file"]);
+        let render_options = RenderOptions::from(&matches);
+        assert_eq!(render_options.camera, Some(String::from("top")));
+
+        let matches = parse_arguments(&["x", "--all-cameras", "file"]);
+        let render_options = RenderOptions::from(&matches);
+        assert_eq!(render_options.all_cameras, true);
     }
 }
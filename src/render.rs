@@ -14,18 +14,22 @@ collector thread uses an async channel with "unlimited buffer". That means that
 window has locked the imagebuffer and the collector cannot read the channels, the render threads
 can still send new pixel data to the channel.
 */
-use std::path::Path;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Receiver};
 use std::sync::Arc;
 use std::thread::JoinHandle;
 use std::thread;
+use std::time::Instant;
 
 extern crate image;
 use image::Rgba;
+use png::{self, HasParameters};
 use time::{PreciseTime, Duration};
 use parking_lot::{Mutex, RwLock};
 
-use raingun::{Scene, RenderedPixel};
+use raingun::{Scene, Camera, RenderedPass, RenderedPixel, Eye, Stereo, StereoLayout};
 
 pub type ImageBuffer = image::ImageBuffer<Rgba<u8>, Vec<u8>>;
 
@@ -33,6 +37,13 @@ pub struct RenderOptions {
     pub width: u32,
     pub height: u32,
     pub max_recursion_depth: Option<u32>,
+    pub samples: Option<u32>,
+    pub camera: Option<String>,
+    pub all_cameras: bool,
+    pub progressive: bool,
+    pub write_passes: bool,
+    // In seconds.
+    pub time_limit: Option<f64>,
 }
 
 impl Default for RenderOptions {
@@ -41,92 +52,292 @@ impl Default for RenderOptions {
             width: 800,
             height: 600,
             max_recursion_depth: None,
+            samples: None,
+            camera: None,
+            all_cameras: false,
+            progressive: false,
+            write_passes: false,
+            time_limit: None,
         }
     }
 }
 
 
-pub fn render_image_without_preview(scene: &Scene,
+pub fn render_image_without_preview(scene: &mut Scene,
                                     render_options: &RenderOptions,
+                                    deadline: Option<Instant>,
                                     input_path: &Path,
                                     output_path: &Path) {
+    let width = render_options.width;
+    let height = render_options.height;
+    let stereo = scene.camera.stereo;
+
+    let (image_width, image_height) = output_size(stereo, width, height);
+    let mut image: ImageBuffer = image::ImageBuffer::new(image_width, image_height);
 
+    let mut samples = Vec::new();
     let render_start = PreciseTime::now();
-    let image = scene.render_image(render_options.width, render_options.height);
+    let views = views(scene);
+    let view_count = views.len();
+    for (index, (camera, eye)) in views.into_iter().enumerate() {
+        limit_time(scene, next_deadline(deadline, view_count - index));
+
+        let last_pass = if renders_in_passes(scene, render_options) {
+            scene.progressive_render_with_camera(&camera, width, height, |pass| {
+                place_image(&mut image, &pass.image, stereo, eye);
+                if render_options.write_passes {
+                    let samples = with_pass(&samples, pass, width, height);
+                    save_image(&image, stereo, output_path, &samples);
+                }
+            })
+        } else {
+            let pass = scene.render_image_with_camera(&camera, width, height);
+            place_image(&mut image, &pass.image, stereo, eye);
+            pass
+        };
+        samples.push(average_samples(&last_pass, width, height));
+    }
     let render_end = PreciseTime::now();
 
-    image.save(&output_path).expect("Could not encode image");
+    save_image(&image, stereo, output_path, &samples);
 
     let write_end = PreciseTime::now();
 
     print_render_message(&input_path,
                          &output_path,
                          render_start.to(render_end),
-                         render_end.to(write_end));
+                         render_end.to(write_end),
+                         &samples);
 }
 
-pub fn render_image_with_preview(scene: &Scene,
+pub fn render_image_with_preview(scene: &mut Scene,
                                  render_options: &RenderOptions,
+                                 deadline: Option<Instant>,
                                  input_path: &Path,
                                  output_path: &Path) {
-    // Create shared image buffer
-    let shared_image: ImageBuffer = image::ImageBuffer::new(render_options.width,
-                                                            render_options.height);
-    let shared_image = Arc::new(Mutex::new(shared_image));
+    let width = render_options.width;
+    let height = render_options.height;
+    let stereo = scene.camera.stereo;
 
-    // Create render thread channel
-    let (channel_tx, channel_rx) = channel();
+    // Create shared image buffer. Stereo cameras render both eyes into the same buffer.
+    let (image_width, image_height) = output_size(stereo, width, height);
+    let shared_image: ImageBuffer = image::ImageBuffer::new(image_width, image_height);
+    let shared_image = Arc::new(Mutex::new(shared_image));
 
     // Create window
     let close_window_condition = Arc::new(RwLock::new(false));
     let window_thread = start_window_thread(shared_image.clone(),
-                                            render_options,
+                                            image_width,
+                                            image_height,
                                             close_window_condition.clone());
 
-    // Start collector thread
-    let collector_thread = start_collector_thread(channel_rx, shared_image.clone());
-
-    // Start rendering threads
+    // Start rendering threads, with a collector thread and channel for each rendered view
+    let mut samples = Vec::new();
     let render_start = PreciseTime::now();
-    scene.streaming_render(render_options.width, render_options.height, channel_tx);
+    let views = views(scene);
+    let view_count = views.len();
+    for (index, (camera, eye)) in views.into_iter().enumerate() {
+        limit_time(scene, next_deadline(deadline, view_count - index));
+
+        let (channel_tx, channel_rx) = channel();
+        let collector_thread = start_collector_thread(channel_rx,
+                                                      shared_image.clone(),
+                                                      move |image, pixel| {
+            put_view_pixel(image, stereo, eye, pixel.x, pixel.y, pixel.color.rgba())
+        });
+
+        let last_pass = if renders_in_passes(scene, render_options) {
+            // Progressive rendering streams the running average of every pass, so the preview
+            // fills up with a rough image first and is then refined.
+            let write_pass = |pass: &RenderedPass| if render_options.write_passes {
+                let mut image = shared_image.lock();
+                place_image(&mut image, &pass.image, stereo, eye);
+                save_image(&image, stereo, output_path, &with_pass(&samples, pass, width, height));
+            };
+            scene.streaming_progressive_render_with_camera(&camera,
+                                                           width,
+                                                           height,
+                                                           channel_tx,
+                                                           write_pass)
+        } else {
+            scene.streaming_render_with_camera(&camera, width, height, channel_tx)
+        };
+        samples.push(average_samples(&last_pass, width, height));
+
+        collector_thread.join().unwrap();
+    }
     let render_end = PreciseTime::now();
 
-    // When done, close channel
-    //    ...on closed channel, close window and stop collector.
-    // (This happens in the other threads)
+    // When done, the channels are closed and the collectors stop after processing what was left
+    // in them.
 
     // Store image buffer to output file when everything is done
     let write_end = {
         let shared_image = shared_image.lock();
-
-        shared_image
-            .save(&output_path)
-            .expect("Could not encode image");
-
+        save_image(&shared_image, stereo, output_path, &samples);
         PreciseTime::now()
     };
 
     print_render_message(&input_path,
                          &output_path,
                          render_start.to(render_end),
-                         render_end.to(write_end));
+                         render_end.to(write_end),
+                         &samples);
 
     // Exit
     {
         let mut close = close_window_condition.write();
         *close = true;
     }
-    collector_thread.join().unwrap();
     window_thread.join().unwrap();
 }
 
+// Rendering in passes is needed to stop early because of the scene's noise threshold or time
+// limit.
+fn renders_in_passes(scene: &Scene, render_options: &RenderOptions) -> bool {
+    render_options.progressive || scene.noise_threshold.is_some() || scene.time_limit.is_some()
+}
+
+// Deadline for the next of a number of renders that share the time until the deadline evenly,
+// including the next one.
+pub fn next_deadline(deadline: Option<Instant>, renders_left: usize) -> Option<Instant> {
+    deadline.map(|deadline| {
+        let now = Instant::now();
+        if deadline > now {
+            now + (deadline - now) / renders_left.max(1) as u32
+        } else {
+            now
+        }
+    })
+}
+
+// Deadline for a time limit in seconds that starts now.
+pub fn deadline_after(seconds: f64) -> Instant {
+    Instant::now() + std::time::Duration::from_secs_f64(seconds.max(0.0))
+}
+
+// Makes the scene stop rendering at the deadline.
+fn limit_time(scene: &mut Scene, deadline: Option<Instant>) {
+    if let Some(deadline) = deadline {
+        let left = deadline.saturating_duration_since(Instant::now());
+        scene.time_limit = Some(left.as_secs_f64());
+    }
+}
+
+// The cameras to render, with the eye they are for when rendering in stereo.
+fn views(scene: &Scene) -> Vec<(Camera, Option<Eye>)> {
+    match scene.camera.stereo {
+        None => vec![(scene.camera.clone(), None)],
+        Some(_) => {
+            vec![(scene.camera.eye(Eye::Left), Some(Eye::Left)),
+                 (scene.camera.eye(Eye::Right), Some(Eye::Right))]
+        }
+    }
+}
+
+fn output_size(stereo: Option<Stereo>, width: u32, height: u32) -> (u32, u32) {
+    match stereo {
+        Some(stereo) => stereo.layout.composite_size(width, height),
+        None => (width, height),
+    }
+}
+
+fn put_view_pixel(image: &mut ImageBuffer,
+                  stereo: Option<Stereo>,
+                  eye: Option<Eye>,
+                  x: u32,
+                  y: u32,
+                  color: Rgba<u8>) {
+    match (stereo, eye) {
+        (Some(stereo), Some(eye)) => stereo.layout.put_pixel(image, eye, x, y, color),
+        _ => image.put_pixel(x, y, color),
+    }
+}
+
+fn place_image(image: &mut ImageBuffer,
+               view_image: &ImageBuffer,
+               stereo: Option<Stereo>,
+               eye: Option<Eye>) {
+    for (x, y, pixel) in view_image.enumerate_pixels() {
+        put_view_pixel(image, stereo, eye, x, y, *pixel);
+    }
+}
+
+// Saves the image, along with the average number of samples per pixel of every view that has
+// been rendered into it so far.
+fn save_image(image: &ImageBuffer, stereo: Option<Stereo>, output_path: &Path, samples: &[f64]) {
+    match stereo {
+        Some(Stereo { layout: StereoLayout::SeparateFiles, .. }) => {
+            // Separate eyes are composited side by side; split them up again.
+            let width = image.width() / 2;
+            let eyes = [(Eye::Left, 0), (Eye::Right, width)];
+            for (index, &(eye, offset)) in eyes.iter().enumerate() {
+                let eye_image: ImageBuffer = image::ImageBuffer::from_fn(width,
+                                                                         image.height(),
+                                                                         |x, y| {
+                    *image.get_pixel(x + offset, y)
+                });
+
+                let eye_samples = samples.get(index).map(std::slice::from_ref).unwrap_or(&[]);
+                write_image(&eye_image, &suffixed_path(output_path, eye.name()), eye_samples);
+            }
+        }
+        _ => write_image(image, output_path, samples),
+    }
+}
+
+// Saves the image in the format of the path's extension. PNG images get the number of samples
+// per pixel as a comment, which the image crate's encoder has no way to add.
+fn write_image(image: &ImageBuffer, path: &Path, samples: &[f64]) {
+    let is_png = path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+
+    if is_png && !samples.is_empty() {
+        let file = File::create(path).expect("Could not create image file");
+        write_png(BufWriter::new(file), image, &samples_text(samples))
+            .expect("Could not encode image");
+    } else {
+        image.save(path).expect("Could not encode image");
+    }
+}
+
+// Encodes the image as a PNG with the comment in a tEXt chunk.
+fn write_png<W: Write>(output: W,
+                       image: &ImageBuffer,
+                       comment: &str)
+                       -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(output, image.width(), image.height());
+    encoder.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_chunk(*b"tEXt", format!("Comment\0{}", comment).as_bytes())?;
+    writer.write_image_data(image)
+}
+
+// Adds a suffix to the filename, before the extension. "out.png" with suffix "left" becomes
+// "out-left.png".
+pub fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_stem()
+        .map(|stem| stem.to_os_string())
+        .unwrap_or_default();
+
+    file_name.push("-");
+    file_name.push(suffix);
+
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+
+    path.with_file_name(file_name)
+}
+
 fn start_window_thread(shared_image: Arc<Mutex<ImageBuffer>>,
-                       render_options: &RenderOptions,
+                       width: u32,
+                       height: u32,
                        close_condition: Arc<RwLock<bool>>)
                        -> JoinHandle<()> {
-    let width = render_options.width;
-    let height = render_options.height;
-
     thread::spawn(move || {
         use piston_window::*;
         let mut window: PistonWindow = WindowSettings::new("Raingun", (width, height))
@@ -190,9 +401,12 @@ fn start_window_thread(shared_image: Arc<Mutex<ImageBuffer>>,
     })
 }
 
-fn start_collector_thread(channel_rx: Receiver<RenderedPixel>,
-                          shared_image: Arc<Mutex<ImageBuffer>>)
-                          -> JoinHandle<()> {
+fn start_collector_thread<F>(channel_rx: Receiver<RenderedPixel>,
+                             shared_image: Arc<Mutex<ImageBuffer>>,
+                             put_pixel: F)
+                             -> JoinHandle<()>
+    where F: Fn(&mut ImageBuffer, RenderedPixel) + Send + 'static
+{
     thread::spawn(move || {
         // rustfmt has a bug when it formats this while loop. It can be solved by having this
         // comment here.
@@ -202,9 +416,7 @@ fn start_collector_thread(channel_rx: Receiver<RenderedPixel>,
             match message {
                 Ok(rendered_pixel) => {
                     let mut image = shared_image.lock();
-                    image.put_pixel(rendered_pixel.x,
-                                    rendered_pixel.y,
-                                    rendered_pixel.color.rgba());
+                    put_pixel(&mut image, rendered_pixel);
                 }
                 Err(_) => {
                     // Channel was closed, abort collector loop.
@@ -215,15 +427,46 @@ fn start_collector_thread(channel_rx: Receiver<RenderedPixel>,
     })
 }
 
+// Number of samples per pixel, on average over the whole image. Pixels can get different numbers
+// of samples with adaptive sampling or a noise threshold.
+fn average_samples(pass: &RenderedPass, width: u32, height: u32) -> f64 {
+    pass.samples as f64 / (width as f64 * height as f64).max(1.0)
+}
+
+// The samples of the views that were rendered before the pass, and of the pass' own view.
+fn with_pass(samples: &[f64], pass: &RenderedPass, width: u32, height: u32) -> Vec<f64> {
+    let mut samples = samples.to_vec();
+    samples.push(average_samples(pass, width, height));
+    samples
+}
+
+fn format_samples(samples: f64) -> String {
+    if samples.fract() == 0.0 {
+        format!("{}", samples)
+    } else {
+        format!("{:.2}", samples)
+    }
+}
+
+// Stereo images list the samples of each eye.
+fn samples_text(samples: &[f64]) -> String {
+    let counts: Vec<String> = samples.iter().map(|&count| format_samples(count)).collect();
+    format!("{} samples per pixel", counts.join(" and "))
+}
+
 fn print_render_message(input_path: &Path,
                         output_path: &Path,
                         render_duration: Duration,
-                        write_duration: Duration) {
-    println!("{input}\t→\t{output}\t({render_duration} render, {write_duration} write)",
+                        write_duration: Duration,
+                        samples: &[f64]) {
+    let samples = format!(", {}", samples_text(samples));
+
+    println!("{input}\t→\t{output}\t({render_duration} render, {write_duration} write{samples})",
              input = input_path.to_string_lossy(),
              output = output_path.to_string_lossy(),
              render_duration = format_duration(render_duration),
-             write_duration = format_duration(write_duration));
+             write_duration = format_duration(write_duration),
+             samples = samples);
 }
 
 fn format_duration(duration: Duration) -> String {
@@ -242,3 +485,50 @@ fn format_duration(duration: Duration) -> String {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_adds_suffixes_before_the_extension() {
+        assert_eq!(suffixed_path(Path::new("out/scene.png"), "left"),
+                   PathBuf::from("out/scene-left.png"));
+        assert_eq!(suffixed_path(Path::new("scene"), "right"),
+                   PathBuf::from("scene-right"));
+    }
+
+    #[test]
+    fn it_formats_average_samples_per_pixel() {
+        assert_eq!(format_samples(16.0), "16");
+        assert_eq!(format_samples(1.0 + 2.0 / 3.0), "1.67");
+    }
+
+    #[test]
+    fn it_writes_the_samples_into_pngs() {
+        let image: ImageBuffer = image::ImageBuffer::from_pixel(4, 2, Rgba([10, 20, 30, 255]));
+        let mut output = Vec::new();
+        write_png(&mut output, &image, &samples_text(&[16.0, 12.5])).unwrap();
+
+        let comment = b"tEXtComment\x0016 and 12.50 samples per pixel";
+        assert!(output.windows(comment.len()).any(|window| window == &comment[..]));
+
+        let decoded = image::load_from_memory(&output).unwrap().to_rgba();
+        assert_eq!(decoded.into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn it_shares_the_time_left_between_renders() {
+        assert_eq!(next_deadline(None, 2), None);
+
+        let start = Instant::now();
+        let deadline = start + std::time::Duration::from_secs(60);
+        let half = next_deadline(Some(deadline), 2).unwrap();
+        assert!(half >= start + std::time::Duration::from_secs(30));
+        assert!(half < start + std::time::Duration::from_secs(31));
+        assert!(next_deadline(Some(deadline), 1).unwrap() == deadline);
+
+        let past = Instant::now();
+        assert!(next_deadline(Some(past), 3).unwrap() >= past);
+    }
+}